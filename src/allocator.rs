@@ -1,50 +1,303 @@
+pub mod arena;
+pub mod buddy;
 pub mod bump;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
 pub mod fixed_size_block;
 pub mod linked_list;
+pub mod oom;
+pub mod slab;
+#[cfg(feature = "heap-track")]
+pub mod track;
 
+use crate::memory::vmm::{self, VirtualRegion, VmmError};
+use conquer_once::spin::OnceCell;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+use fixed_size_block::BLOCK_SIZES;
+#[cfg(feature = "alloc-fixed-block")]
 use fixed_size_block::FixedSizeBlockAllocator;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 use x86_64::{
     VirtAddr,
-    structures::paging::{
-        FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, mapper::MapToError,
-    },
+    instructions::interrupts,
+    structures::paging::{Page, PageSize, PageTableFlags, Size4KiB, page::PageRange},
 };
 
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-fixed-block",
+    feature = "alloc-buddy"
+)))]
+compile_error!("select a global allocator with one of the `alloc-*` features");
+
+#[cfg(any(
+    all(feature = "alloc-bump", feature = "alloc-linked-list"),
+    all(feature = "alloc-bump", feature = "alloc-fixed-block"),
+    all(feature = "alloc-bump", feature = "alloc-buddy"),
+    all(feature = "alloc-linked-list", feature = "alloc-fixed-block"),
+    all(feature = "alloc-linked-list", feature = "alloc-buddy"),
+    all(feature = "alloc-fixed-block", feature = "alloc-buddy"),
+))]
+compile_error!("only one of the `alloc-*` features can be enabled");
+
+/// The allocator used as the global allocator, selected by the `alloc-*`
+/// cargo features.
+#[cfg(feature = "alloc-bump")]
+pub type GlobalHeap = bump::BumpAllocator;
+#[cfg(feature = "alloc-linked-list")]
+pub type GlobalHeap = linked_list::LinkedListAllocator;
+#[cfg(feature = "alloc-fixed-block")]
+pub type GlobalHeap = FixedSizeBlockAllocator;
+#[cfg(feature = "alloc-buddy")]
+pub type GlobalHeap = buddy::BuddyAllocator;
+
+static ALLOCATOR: Locked<GlobalHeap> = Locked::new(GlobalHeap::new());
+
+/// With the `heap-debug` feature, all allocations go through the debug
+/// allocator, which checks them before passing them on to `ALLOCATOR`.
+#[cfg(feature = "heap-debug")]
+static DEBUG_ALLOCATOR: debug::DebugAllocator<GlobalHeap> =
+    debug::DebugAllocator::new(&ALLOCATOR);
+
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static KERNEL_ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// The global allocator, which passes all requests on to `ALLOCATOR`.
+///
+/// Depending on the enabled features, the requests are checked by the debug
+/// allocator (`heap-debug`) and live allocations are tracked (`heap-track`).
+///
+/// If an allocation fails, the memory pressure callbacks registered in `oom`
+/// get a chance to free memory before the allocation is retried once. If it
+/// still fails, the heap statistics are printed before the null pointer is
+/// returned, at most once per `oom::REPORT_INTERVAL` timer ticks.
+struct KernelAllocator;
+
+/// Returns the allocator that `KernelAllocator` passes requests to.
+#[cfg(feature = "heap-debug")]
+fn backend() -> &'static impl GlobalAlloc {
+    &DEBUG_ALLOCATOR
+}
+
+/// Returns the allocator that `KernelAllocator` passes requests to.
+#[cfg(not(feature = "heap-debug"))]
+fn backend() -> &'static impl GlobalAlloc {
+    &ALLOCATOR
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut ptr = unsafe { backend().alloc(layout) };
+        if ptr.is_null() && oom::relieve_pressure(layout) {
+            ptr = unsafe { backend().alloc(layout) };
+        }
+        if ptr.is_null() {
+            oom::report_failure(layout);
+        }
+        #[cfg(feature = "heap-track")]
+        if !ptr.is_null() {
+            track::record_alloc(ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-track")]
+        track::record_dealloc(ptr);
+        unsafe { backend().dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let mut new_ptr = unsafe { backend().realloc(ptr, layout, new_size) };
+        if new_ptr.is_null() && oom::relieve_pressure(new_layout) {
+            new_ptr = unsafe { backend().realloc(ptr, layout, new_size) };
+        }
+        if new_ptr.is_null() {
+            oom::report_failure(new_layout);
+        }
+        #[cfg(feature = "heap-track")]
+        if !new_ptr.is_null() {
+            track::record_dealloc(ptr);
+            track::record_alloc(new_ptr, new_layout);
+        }
+        new_ptr
+    }
+}
 
 // Heap memory region
-pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// The size the heap is allowed to grow to on demand.
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
 
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
-    }
+/// The address space reserved for the heap, `HEAP_MAX_SIZE` bytes large.
+static HEAP_REGION: OnceCell<VirtualRegion> = OnceCell::uninit();
+
+/// Reserves and maps the initial heap region and initializes the global
+/// allocator.
+///
+/// Requires that `memory::init_global` was called before.
+pub fn init_heap() -> Result<(), VmmError> {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = vmm::reserve((HEAP_MAX_SIZE as u64).div_ceil(Size4KiB::SIZE), flags)?;
+    let heap_start = region.start();
+    vmm::map_pages(&region, heap_pages(heap_start, heap_start + HEAP_SIZE))?;
+    HEAP_REGION
+        .try_init_once(|| region)
+        .expect("init_heap should only be called once");
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR
+            .lock()
+            .init(heap_start.as_u64() as usize, HEAP_SIZE);
     }
 
     Ok(())
 }
 
+/// Returns the statistics of the global allocator.
+///
+/// The usage does not include the headers and redzones the debug allocator
+/// adds to every allocation.
+#[cfg(feature = "heap-debug")]
+pub fn heap_stats() -> HeapStats {
+    DEBUG_ALLOCATOR.stats()
+}
+
+/// Returns the statistics of the global allocator.
+#[cfg(not(feature = "heap-debug"))]
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// A snapshot of the usage of a heap allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The size of the memory managed by the allocator in bytes.
+    pub total_size: usize,
+    /// The bytes requested by all live allocations, without padding.
+    pub used_bytes: usize,
+    /// The largest value `used_bytes` ever had.
+    pub peak_used_bytes: usize,
+    /// The number of live allocations.
+    pub allocations: usize,
+    /// The number of free blocks of each size in `BLOCK_SIZES`. Always zero
+    /// for allocators without block lists.
+    pub free_blocks: [usize; BLOCK_SIZES.len()],
+    /// The free bytes of the underlying heap. For the fixed size block
+    /// allocator this is the fallback heap, so free blocks are not included.
+    pub free_bytes: usize,
+    /// The size of the largest contiguous free block of the underlying heap.
+    pub largest_free_block: usize,
+}
+
+/// An allocator that keeps track of its usage.
+pub trait AllocatorStats {
+    fn stats(&self) -> HeapStats;
+}
+
+/// An allocator that manages a heap and can be used as the global allocator.
+pub trait HeapAllocator: AllocatorStats {
+    /// The number of bytes at the start of a freed block that the allocator
+    /// overwrites with its own metadata.
+    const FREE_METADATA_SIZE: usize;
+
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// The allocator may grow the heap beyond `heap_size` on demand, as long
+    /// as the heap stays inside the region reserved by `init_heap`.
+    ///
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+}
+
+/// Usage counters shared by all allocators, updated on every allocation and
+/// deallocation.
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    used_bytes: usize,
+    peak_used_bytes: usize,
+    allocations: usize,
+}
+
+impl Usage {
+    const fn new() -> Self {
+        Usage {
+            used_bytes: 0,
+            peak_used_bytes: 0,
+            allocations: 0,
+        }
+    }
+
+    fn record_alloc(&mut self, layout: Layout) {
+        self.used_bytes += layout.size();
+        self.peak_used_bytes = self.peak_used_bytes.max(self.used_bytes);
+        self.allocations += 1;
+    }
+
+    fn record_dealloc(&mut self, layout: Layout) {
+        self.used_bytes -= layout.size();
+        self.allocations -= 1;
+    }
+
+    fn record_realloc(&mut self, layout: Layout, new_layout: Layout) {
+        self.record_dealloc(layout);
+        self.record_alloc(new_layout);
+    }
+
+    /// Returns stats with the counters filled in and everything else zero.
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            used_bytes: self.used_bytes,
+            peak_used_bytes: self.peak_used_bytes,
+            allocations: self.allocations,
+            ..HeapStats::default()
+        }
+    }
+}
+
+/// Maps more pages after `heap_end` so that an allocation of `layout` fits
+/// into the heap.
+///
+/// Returns the number of bytes the heap grew by, or `None` if the heap already
+/// reached `HEAP_MAX_SIZE` or no memory could be mapped.
+fn grow_heap(heap_end: usize, layout: Layout) -> Option<usize> {
+    let region = HEAP_REGION.get()?;
+    let heap_end = VirtAddr::new(heap_end as u64);
+    let heap_limit = region.start() + region.size();
+    if heap_end < region.start() || heap_end >= heap_limit {
+        return None;
+    }
+
+    // leave room for the alignment padding and grow by at least `HEAP_SIZE`
+    let wanted = layout.size().checked_add(layout.align())?.max(HEAP_SIZE) as u64;
+    let size = x86_64::align_up(wanted, Size4KiB::SIZE).min(heap_limit - heap_end);
+
+    vmm::map_pages(region, heap_pages(heap_end, heap_end + size)).ok()?;
+    Some(size as usize)
+}
+
+/// Returns the pages containing the heap addresses `start..end`.
+fn heap_pages(start: VirtAddr, end: VirtAddr) -> PageRange {
+    Page::range(
+        Page::containing_address(start),
+        Page::containing_address(end - 1u64) + 1,
+    )
+}
+
+/// A spinlock around an allocator that disables interrupts while it is held.
+///
+/// Otherwise an interrupt handler that allocates while the interrupted code
+/// holds the lock would spin forever.
 pub struct Locked<A> {
     inner: Mutex<A>,
 }
@@ -56,9 +309,86 @@ impl<A> Locked<A> {
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
-        self.inner.lock()
+    /// Disables interrupts and locks the allocator. Interrupts are enabled
+    /// again when the guard is dropped, if they were enabled before.
+    pub fn lock(&self) -> LockedGuard<'_, A> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        LockedGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+        }
+    }
+
+    /// Like `lock`, but returns `None` instead of spinning if the allocator
+    /// is already locked.
+    pub fn try_lock(&self) -> Option<LockedGuard<'_, A>> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        let Some(guard) = self.inner.try_lock() else {
+            if interrupts_enabled {
+                interrupts::enable();
+            }
+            return None;
+        };
+        Some(LockedGuard {
+            guard: ManuallyDrop::new(guard),
+            interrupts_enabled,
+        })
+    }
+}
+
+/// The guard returned by `Locked::lock`.
+pub struct LockedGuard<'a, A> {
+    guard: ManuallyDrop<MutexGuard<'a, A>>,
+    interrupts_enabled: bool,
+}
+
+impl<A> Deref for LockedGuard<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.guard
+    }
+}
+
+impl<A> DerefMut for LockedGuard<'_, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        &mut self.guard
+    }
+}
+
+impl<A> Drop for LockedGuard<'_, A> {
+    fn drop(&mut self) {
+        // unlock before enabling interrupts, so that a pending interrupt can
+        // take the lock
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Reallocates by allocating a new block, copying the contents over and
+/// freeing the old block, for when an allocator can not resize in place.
+///
+/// # Safety
+/// Same as `GlobalAlloc::realloc`. The allocator must not be locked.
+unsafe fn realloc_by_copy(
+    allocator: &impl GlobalAlloc,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+) -> *mut u8 {
+    let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+    let new_ptr = unsafe { allocator.alloc(new_layout) };
+    if !new_ptr.is_null() {
+        unsafe {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            allocator.dealloc(ptr, layout);
+        }
     }
+    new_ptr
 }
 
 /// Align the given address `addr` upwards to alignment `align`.
@@ -0,0 +1,177 @@
+use super::{AllocatorStats, HeapAllocator, HeapStats, Locked, bump::BumpAllocator};
+use crate::memory::vmm::{self, VirtualRegion, VmmError};
+use alloc::alloc::{alloc, dealloc};
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::{self, NonNull},
+};
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
+
+/// The alignment of chunks taken from the global heap.
+const CHUNK_ALIGN: usize = 16;
+
+/// The memory an arena allocates from.
+#[derive(Debug)]
+enum Chunk {
+    /// A chunk of the global heap.
+    Heap { start: usize, layout: Layout },
+    /// Pages mapped for the arena only.
+    Pages(VirtualRegion),
+}
+
+#[derive(Debug)]
+pub enum ArenaError {
+    /// No chunk of the requested size could be allocated from the global heap.
+    OutOfMemory,
+    /// The pages for the arena could not be reserved or mapped.
+    Vmm(VmmError),
+}
+
+/// A bump allocator over a fixed chunk of memory, usable through the
+/// `Allocator` API, e.g. with `Vec::new_in(&arena)`.
+///
+/// All allocations are freed at once when the arena is reset or dropped, so
+/// short-lived work does not fragment the global heap. The arena does not grow:
+/// allocations fail once the chunk is used up.
+pub struct Arena {
+    bump: Locked<BumpAllocator>,
+    chunk: Chunk,
+}
+
+impl Arena {
+    /// Creates an arena over a chunk of `size` bytes of the global heap.
+    pub fn new(size: usize) -> Result<Arena, ArenaError> {
+        let layout = Layout::from_size_align(size.max(1), CHUNK_ALIGN)
+            .map_err(|_| ArenaError::OutOfMemory)?;
+        let start = unsafe { alloc(layout) };
+        if start.is_null() {
+            return Err(ArenaError::OutOfMemory);
+        }
+        let chunk = Chunk::Heap {
+            start: start as usize,
+            layout,
+        };
+        Ok(unsafe { Arena::with_chunk(start as usize, size, chunk) })
+    }
+
+    /// Creates an arena over `pages` newly mapped pages, which are unmapped
+    /// again when the arena is dropped.
+    pub fn with_pages(pages: u64) -> Result<Arena, ArenaError> {
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let region = vmm::reserve(pages, flags).map_err(ArenaError::Vmm)?;
+        if let Err(err) = vmm::map(&region) {
+            unsafe { vmm::release(region) }.expect("failed to release arena pages");
+            return Err(ArenaError::Vmm(err));
+        }
+        let start = region.start().as_u64() as usize;
+        let size = (pages * Size4KiB::SIZE) as usize;
+        Ok(unsafe { Arena::with_chunk(start, size, Chunk::Pages(region)) })
+    }
+
+    /// # Safety
+    /// The memory `start..start + size` must be unused and belong to `chunk`.
+    unsafe fn with_chunk(start: usize, size: usize, chunk: Chunk) -> Arena {
+        let mut bump = BumpAllocator::new();
+        unsafe { bump.init(start, size) };
+        Arena {
+            bump: Locked::new(bump),
+            chunk,
+        }
+    }
+
+    /// Frees all allocations of the arena.
+    ///
+    /// Allocations borrow the arena, so none of them can be alive anymore.
+    pub fn reset(&mut self) {
+        unsafe { self.bump.lock().reset() };
+    }
+
+    /// Returns the usage of the arena's chunk.
+    pub fn stats(&self) -> HeapStats {
+        self.bump.lock().stats()
+    }
+
+    /// Moves an allocation to a new allocation for `new_layout`, copying
+    /// `min(old_layout.size(), new_layout.size())` bytes.
+    ///
+    /// # Safety
+    /// Same as `Allocator::grow`.
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = self.allocate(new_layout)?;
+        unsafe {
+            let size = old_layout.size().min(new_layout.size());
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, size);
+            self.deallocate(ptr, old_layout);
+        }
+        Ok(new_ptr)
+    }
+
+    /// Tries to resize an allocation in place, which requires that it is
+    /// already aligned for the new layout.
+    fn resize_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<[u8]>> {
+        let aligned = ptr.addr().get().is_multiple_of(new_layout.align());
+        let resized = aligned
+            && self
+                .bump
+                .lock()
+                .resize_in_place(ptr.as_ptr(), old_layout, new_layout.size());
+        resized.then(|| NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let start = self.bump.lock().bump(layout).ok_or(AllocError)?;
+        let ptr = NonNull::new(start as *mut u8).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, layout: Layout) {
+        self.bump.lock().release(layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match self.resize_in_place(ptr, old_layout, new_layout) {
+            Some(resized) => Ok(resized),
+            None => unsafe { self.reallocate(ptr, old_layout, new_layout) },
+        }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match self.resize_in_place(ptr, old_layout, new_layout) {
+            Some(resized) => Ok(resized),
+            None => unsafe { self.reallocate(ptr, old_layout, new_layout) },
+        }
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        match self.chunk {
+            Chunk::Heap { start, layout } => unsafe { dealloc(start as *mut u8, layout) },
+            Chunk::Pages(region) => {
+                unsafe { vmm::release(region) }.expect("failed to release arena pages")
+            }
+        }
+    }
+}
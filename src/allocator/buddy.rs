@@ -0,0 +1,361 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem,
+    ptr::{self, null_mut},
+};
+
+use super::{AllocatorStats, HEAP_MAX_SIZE, HeapAllocator, HeapStats, Locked, Usage, align_up};
+
+/// The order of the smallest block, which must be able to hold a `FreeBlock`.
+const MIN_ORDER: usize = 5;
+/// The order of the largest block, a block spanning the largest possible heap.
+const MAX_ORDER: usize = HEAP_MAX_SIZE.trailing_zeros() as usize;
+/// The number of block sizes.
+const ORDERS: usize = MAX_ORDER - MIN_ORDER + 1;
+/// The number of smallest blocks in the largest possible heap.
+const MAX_BLOCKS: usize = HEAP_MAX_SIZE >> MIN_ORDER;
+
+const _: () = assert!(HEAP_MAX_SIZE.is_power_of_two());
+const _: () = assert!(mem::size_of::<FreeBlock>() <= 1 << MIN_ORDER);
+
+/// The header at the start of every free block.
+struct FreeBlock {
+    order: usize,
+    /// The neighbours in the free list of the block's order.
+    prev: *mut FreeBlock,
+    next: *mut FreeBlock,
+}
+
+/// Returns the order of the block needed for the given layout.
+///
+/// Blocks are aligned to their size relative to the heap start, so the block
+/// must be at least as large as the alignment.
+fn order_for(layout: &Layout) -> Option<usize> {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .max(1 << MIN_ORDER)
+        .checked_next_power_of_two()?;
+    let order = size.trailing_zeros() as usize;
+    (order <= MAX_ORDER).then_some(order)
+}
+
+/// An allocator that splits the heap into blocks of power of two sizes.
+///
+/// A block of order `n` is `2^n` bytes large and either free or split into two
+/// buddies of order `n - 1`. Freed blocks are merged with their buddy whenever
+/// the buddy is free as well, so allocation and deallocation take at most
+/// `ORDERS` steps.
+///
+/// Alignments up to the block size are supported as long as the heap start is
+/// aligned at least as strictly, which holds for the page aligned kernel heap.
+pub struct BuddyAllocator {
+    /// For every order, the free blocks of that order.
+    free_lists: [*mut FreeBlock; ORDERS],
+    /// One bit for every smallest block of the heap, set if a free block
+    /// starts there.
+    free_map: [u64; MAX_BLOCKS / 64],
+    heap_start: usize,
+    heap_end: usize,
+    usage: Usage,
+}
+
+// the free blocks are only accessed through the allocator
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    /// Creates an empty BuddyAllocator.
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        BuddyAllocator {
+            free_lists: [ptr::null_mut(); ORDERS],
+            free_map: [0; MAX_BLOCKS / 64],
+            heap_start: 0,
+            heap_end: 0,
+            usage: Usage::new(),
+        }
+    }
+
+    /// Returns the index of the bit in `free_map` for the block at `addr`.
+    fn map_index(&self, addr: usize) -> usize {
+        (addr - self.heap_start) >> MIN_ORDER
+    }
+
+    fn is_free(&self, addr: usize) -> bool {
+        let index = self.map_index(addr);
+        self.free_map[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set_free(&mut self, addr: usize, free: bool) {
+        let index = self.map_index(addr);
+        if free {
+            self.free_map[index / 64] |= 1 << (index % 64);
+        } else {
+            self.free_map[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    /// Adds the block at `addr` to the free list of its order.
+    unsafe fn push(&mut self, addr: usize, order: usize) {
+        let head = self.free_lists[order - MIN_ORDER];
+        let block = addr as *mut FreeBlock;
+        unsafe {
+            block.write(FreeBlock {
+                order,
+                prev: ptr::null_mut(),
+                next: head,
+            });
+            if !head.is_null() {
+                (*head).prev = block;
+            }
+        }
+        self.free_lists[order - MIN_ORDER] = block;
+        self.set_free(addr, true);
+    }
+
+    /// Removes the free block from the free list of its order.
+    unsafe fn remove(&mut self, block: *mut FreeBlock) {
+        unsafe {
+            let FreeBlock { order, prev, next } = block.read();
+            if prev.is_null() {
+                self.free_lists[order - MIN_ORDER] = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+        self.set_free(block as usize, false);
+    }
+
+    /// Takes a free block of the given order, splitting a larger block if
+    /// there is none.
+    ///
+    /// Returns the address of the block.
+    fn take(&mut self, order: usize) -> Option<usize> {
+        let mut current =
+            (order..=MAX_ORDER).find(|&o| !self.free_lists[o - MIN_ORDER].is_null())?;
+        let block = self.free_lists[current - MIN_ORDER];
+        unsafe { self.remove(block) };
+
+        // put the upper halves back until the block has the wanted order
+        let addr = block as usize;
+        while current > order {
+            current -= 1;
+            unsafe { self.push(addr + (1 << current), current) };
+        }
+        Some(addr)
+    }
+
+    /// Frees the block at `addr`, merging it with its buddy as long as the
+    /// buddy is free and not split.
+    ///
+    /// # Safety
+    /// The block must be part of the heap and unused.
+    unsafe fn free(&mut self, mut addr: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = self.heap_start + ((addr - self.heap_start) ^ (1 << order));
+            let mergeable = buddy + (1 << order) <= self.heap_end
+                && self.is_free(buddy)
+                && unsafe { (*(buddy as *const FreeBlock)).order } == order;
+            if !mergeable {
+                break;
+            }
+            unsafe { self.remove(buddy as *mut FreeBlock) };
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        unsafe { self.push(addr, order) };
+    }
+
+    /// Frees the memory `start..end` as the largest blocks that fit.
+    ///
+    /// # Safety
+    /// The memory must be part of the heap and unused.
+    unsafe fn add_range(&mut self, start: usize, end: usize) {
+        let mut addr = start;
+        while addr + (1 << MIN_ORDER) <= end {
+            let offset = addr - self.heap_start;
+            let order = (MIN_ORDER..=MAX_ORDER)
+                .rev()
+                .find(|&o| offset.is_multiple_of(1 << o) && addr + (1 << o) <= end)
+                .unwrap();
+            unsafe { self.free(addr, order) };
+            addr += 1 << order;
+        }
+    }
+
+    /// Grows the heap so that a block of the given order fits into the new
+    /// part of it.
+    ///
+    /// Returns whether the heap could be grown.
+    fn grow(&mut self, order: usize) -> bool {
+        let layout = Layout::from_size_align(1 << order, 1 << order).unwrap();
+        let Some(grown) = super::grow_heap(self.heap_end, layout) else {
+            return false;
+        };
+        let start = self.heap_end;
+        self.heap_end = (start + grown).min(self.heap_start + HEAP_MAX_SIZE);
+        unsafe { self.add_range(start, self.heap_end) };
+        true
+    }
+}
+
+impl HeapAllocator for BuddyAllocator {
+    const FREE_METADATA_SIZE: usize = mem::size_of::<FreeBlock>();
+
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = align_up(heap_start, 1 << MIN_ORDER);
+        self.heap_end = (heap_start + heap_size).min(self.heap_start + HEAP_MAX_SIZE);
+        unsafe { self.add_range(self.heap_start, self.heap_end) };
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BuddyAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let Some(order) = order_for(&layout) else {
+            return null_mut();
+        };
+        if !allocator.heap_start.is_multiple_of(layout.align()) {
+            return null_mut();
+        }
+
+        let mut block = allocator.take(order);
+        if block.is_none() && allocator.grow(order) {
+            block = allocator.take(order);
+        }
+        match block {
+            Some(addr) => {
+                allocator.usage.record_alloc(layout);
+                addr as *mut u8
+            }
+            None => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        let order = order_for(&layout).expect("invalid layout");
+        allocator.usage.record_dealloc(layout);
+        unsafe { allocator.free(ptr as usize, order) }
+    }
+}
+
+impl AllocatorStats for BuddyAllocator {
+    fn stats(&self) -> HeapStats {
+        let mut free_bytes = 0;
+        let mut largest_free_block = 0;
+        for (order, &head) in (MIN_ORDER..).zip(&self.free_lists) {
+            let mut block = head;
+            while !block.is_null() {
+                free_bytes += 1 << order;
+                largest_free_block = 1 << order;
+                block = unsafe { (*block).next };
+            }
+        }
+
+        HeapStats {
+            total_size: self.heap_end - self.heap_start,
+            free_bytes,
+            largest_free_block,
+            ..self.usage.stats()
+        }
+    }
+}
+
+#[cfg(test)]
+const TEST_HEAP_SIZE: usize = 64 * 1024;
+
+/// The heap of the tests, aligned so that it is a single block.
+#[cfg(test)]
+#[repr(align(65536))]
+struct TestHeap([u8; TEST_HEAP_SIZE]);
+
+#[test_case]
+fn blocks_are_split_and_merged() {
+    static mut HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+    static ALLOCATOR: Locked<BuddyAllocator> = Locked::new(BuddyAllocator::new());
+    unsafe {
+        ALLOCATOR
+            .lock()
+            .init(&raw mut HEAP.0 as usize, TEST_HEAP_SIZE)
+    };
+
+    let stats = ALLOCATOR.lock().stats();
+    assert_eq!(stats.free_bytes, TEST_HEAP_SIZE);
+    assert_eq!(stats.largest_free_block, TEST_HEAP_SIZE);
+
+    // the heap is split down to the smallest block, leaving one free block of
+    // every smaller order
+    let small = Layout::from_size_align(1, 1).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(small) };
+    assert_eq!(ptr, &raw mut HEAP as *mut u8);
+    let stats = ALLOCATOR.lock().stats();
+    assert_eq!(stats.free_bytes, TEST_HEAP_SIZE - (1 << MIN_ORDER));
+    assert_eq!(stats.largest_free_block, TEST_HEAP_SIZE / 2);
+
+    // the buddy of the first block is handed out next
+    let next = unsafe { ALLOCATOR.alloc(small) };
+    assert_eq!(next as usize, ptr as usize + (1 << MIN_ORDER));
+
+    // blocks are aligned to their size
+    let aligned = Layout::from_size_align(8, 4096).unwrap();
+    let aligned_ptr = unsafe { ALLOCATOR.alloc(aligned) };
+    assert!((aligned_ptr as usize).is_multiple_of(4096));
+
+    unsafe {
+        ALLOCATOR.dealloc(ptr, small);
+        ALLOCATOR.dealloc(aligned_ptr, aligned);
+        ALLOCATOR.dealloc(next, small);
+    }
+    let stats = ALLOCATOR.lock().stats();
+    assert_eq!(stats.free_bytes, TEST_HEAP_SIZE);
+    assert_eq!(stats.largest_free_block, TEST_HEAP_SIZE);
+}
+
+#[test_case]
+fn fragmented_heap_merges_completely() {
+    const BLOCKS: usize = TEST_HEAP_SIZE >> MIN_ORDER;
+
+    static mut HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+    static ALLOCATOR: Locked<BuddyAllocator> = Locked::new(BuddyAllocator::new());
+    unsafe {
+        ALLOCATOR
+            .lock()
+            .init(&raw mut HEAP.0 as usize, TEST_HEAP_SIZE)
+    };
+
+    let small = Layout::from_size_align(1 << MIN_ORDER, 1).unwrap();
+    let mut blocks = [null_mut(); BLOCKS];
+    for ptr in blocks.iter_mut() {
+        *ptr = unsafe { ALLOCATOR.alloc(small) };
+        assert!(!ptr.is_null());
+    }
+    assert!(unsafe { ALLOCATOR.alloc(small) }.is_null());
+
+    // with every other block free, no two free blocks are buddies
+    for &ptr in blocks.iter().step_by(2) {
+        unsafe { ALLOCATOR.dealloc(ptr, small) };
+    }
+    let stats = ALLOCATOR.lock().stats();
+    assert_eq!(stats.free_bytes, TEST_HEAP_SIZE / 2);
+    assert_eq!(stats.largest_free_block, 1 << MIN_ORDER);
+    let double = Layout::from_size_align(2 << MIN_ORDER, 1).unwrap();
+    assert!(unsafe { ALLOCATOR.alloc(double) }.is_null());
+
+    for &ptr in blocks.iter().skip(1).step_by(2) {
+        unsafe { ALLOCATOR.dealloc(ptr, small) };
+    }
+    let whole_heap = Layout::from_size_align(TEST_HEAP_SIZE, 1).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(whole_heap) };
+    assert_eq!(ptr, &raw mut HEAP as *mut u8);
+    unsafe { ALLOCATOR.dealloc(ptr, whole_heap) };
+}
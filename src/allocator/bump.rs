@@ -1,11 +1,15 @@
-use super::{Locked, align_up};
-use core::{alloc::GlobalAlloc, ptr::null_mut};
+use super::{AllocatorStats, HeapAllocator, HeapStats, Locked, Usage, align_up};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
 
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
     allocations: usize,
     next: usize,
+    usage: Usage,
 }
 
 impl BumpAllocator {
@@ -16,15 +20,92 @@ impl BumpAllocator {
             heap_end: 0,
             allocations: 0,
             next: 0,
+            usage: Usage::new(),
         }
     }
 
+    /// Frees all allocations at once.
+    ///
+    /// # Safety
+    /// The caller must ensure that none of the allocations is used anymore.
+    pub unsafe fn reset(&mut self) {
+        self.next = self.heap_start;
+        self.allocations = 0;
+        self.usage = Usage {
+            used_bytes: 0,
+            allocations: 0,
+            ..self.usage
+        };
+    }
+
+    /// Allocates memory for `layout` if it fits into the heap, without
+    /// growing the heap.
+    ///
+    /// Returns the start address of the allocation.
+    pub(super) fn bump(&mut self, layout: Layout) -> Option<usize> {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = alloc_start.checked_add(layout.size())?;
+        if alloc_end > self.heap_end {
+            return None;
+        }
+
+        self.next = alloc_end;
+        self.allocations += 1;
+        self.usage.record_alloc(layout);
+        Some(alloc_start)
+    }
+
+    /// Frees an allocation, resetting the allocator if it was the last one.
+    pub(super) fn release(&mut self, layout: Layout) {
+        self.usage.record_dealloc(layout);
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+
+    /// Tries to resize the allocation at `ptr` to `new_size` bytes without
+    /// moving it or growing the heap. This works for the last allocation,
+    /// and for shrinking any other allocation.
+    ///
+    /// Returns whether the allocation was resized.
+    pub(super) fn resize_in_place(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> bool {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return false;
+        };
+        let start = ptr as usize;
+
+        if start + layout.size() == self.next {
+            // the last allocation can be resized by moving `next`
+            match start.checked_add(new_size) {
+                Some(new_end) if new_end <= self.heap_end => self.next = new_end,
+                _ => return false,
+            }
+        } else if new_size > layout.size() {
+            // the end of other allocations is only reused after a reset
+            return false;
+        }
+
+        self.usage.record_realloc(layout, new_layout);
+        true
+    }
+}
+
+impl HeapAllocator for BumpAllocator {
+    /// Freed memory is never reused, so it is left untouched.
+    const FREE_METADATA_SIZE: usize = 0;
+
     /// Initializes the bump allocator with the given heap bounds.
     ///
-    /// # Safetyy
+    /// # Safety
     /// This method is unsafe because the caller must ensure that the given
     /// memory range is unused. Also, this method must be called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
@@ -32,30 +113,85 @@ impl BumpAllocator {
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut bump = self.lock();
+        if let Some(alloc_start) = bump.bump(layout) {
+            return alloc_start as *mut u8;
+        }
 
-        let alloc_start = align_up(bump.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return null_mut(),
-        };
-
-        if alloc_end > bump.heap_end {
-            null_mut() // Out of memory
-        } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
+        // the new pages directly follow the current end of the heap
+        if let Some(size) = super::grow_heap(bump.heap_end, layout) {
+            bump.heap_end += size;
+        }
+        match bump.bump(layout) {
+            Some(alloc_start) => alloc_start as *mut u8,
+            None => null_mut(), // Out of memory
         }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
+        self.lock().release(layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
         let mut bump = self.lock();
 
-        bump.allocations -= 1;
-        if bump.allocations == 0 {
-            bump.next = bump.heap_start;
+        let is_last = ptr as usize + layout.size() == bump.next;
+        if let Some(new_end) = (ptr as usize).checked_add(new_size)
+            && is_last
+            && new_end > bump.heap_end
+        {
+            let missing = Layout::from_size_align(new_end - bump.heap_end, 1).unwrap();
+            if let Some(size) = super::grow_heap(bump.heap_end, missing) {
+                bump.heap_end += size;
+            }
+        }
+        if bump.resize_in_place(ptr, layout, new_size) {
+            return ptr;
+        }
+
+        drop(bump);
+        unsafe { super::realloc_by_copy(self, ptr, layout, new_size) }
+    }
+}
+
+impl AllocatorStats for BumpAllocator {
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            total_size: self.heap_end - self.heap_start,
+            free_bytes: self.heap_end - self.next,
+            largest_free_block: self.heap_end - self.next,
+            ..self.usage.stats()
         }
     }
 }
+
+#[test_case]
+fn last_allocation_is_resized_in_place() {
+    const HEAP_SIZE: usize = 4096;
+
+    static mut HEAP: [u64; HEAP_SIZE / 8] = [0; HEAP_SIZE / 8];
+
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(&raw mut HEAP as usize, HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    let last = unsafe { allocator.alloc(layout) };
+    unsafe { last.write_bytes(0x42, 64) };
+
+    // the last allocation grows and shrinks in place
+    let grown = unsafe { allocator.realloc(last, layout, 256) };
+    assert_eq!(grown, last);
+    assert_eq!(unsafe { last.add(63).read() }, 0x42);
+    let grown_layout = Layout::from_size_align(256, 8).unwrap();
+    let shrunk = unsafe { allocator.realloc(last, grown_layout, 32) };
+    assert_eq!(shrunk, last);
+    assert_eq!(allocator.lock().stats().free_bytes, HEAP_SIZE - 64 - 32);
+
+    // any other allocation is moved when it grows
+    unsafe { first.write_bytes(0x17, 64) };
+    let moved = unsafe { allocator.realloc(first, layout, 128) };
+    assert_ne!(moved, first);
+    assert_eq!(unsafe { moved.add(63).read() }, 0x17);
+}
@@ -0,0 +1,164 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem, ptr, slice,
+};
+
+use super::{HeapAllocator, HeapStats, Locked, Usage, align_up};
+
+/// The size of the redzones before and after every allocation.
+const REDZONE_SIZE: usize = 16;
+/// Fills the redzones of live allocations.
+const CANARY: u8 = 0xfd;
+/// Fills newly allocated memory.
+const ALLOC_POISON: u8 = 0xaa;
+/// Fills freed memory, including the redzones.
+const FREE_POISON: u8 = 0xdd;
+
+/// Stored in front of the redzone before every allocation.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Header {
+    size: usize,
+    align: usize,
+}
+
+/// A wrapper around the global allocator that detects heap corruption.
+///
+/// Every allocation is surrounded by canary redzones, fresh and freed memory
+/// are filled with distinct poison patterns, and `dealloc` panics with the
+/// offending address and layout if a redzone was overwritten, the memory was
+/// already freed or the layout does not match the one it was allocated with.
+///
+/// Double frees are only detected as long as the memory was not allocated
+/// again in between.
+pub struct DebugAllocator<H: 'static> {
+    inner: &'static Locked<H>,
+    /// The usage in the sizes callers asked for, as the inner allocator also
+    /// counts the headers and redzones.
+    usage: Locked<Usage>,
+}
+
+impl<H> DebugAllocator<H> {
+    pub const fn new(inner: &'static Locked<H>) -> Self {
+        DebugAllocator {
+            inner,
+            usage: Locked::new(Usage::new()),
+        }
+    }
+}
+
+impl<H: HeapAllocator> DebugAllocator<H> {
+    /// Returns the statistics of the inner allocator, with the usage counted
+    /// without headers and redzones.
+    pub fn stats(&self) -> HeapStats {
+        let usage = self.usage.lock().stats();
+        HeapStats {
+            used_bytes: usage.used_bytes,
+            peak_used_bytes: usage.peak_used_bytes,
+            allocations: usage.allocations,
+            ..self.inner.lock().stats()
+        }
+    }
+}
+
+/// Returns the offset of the user data from the start of the underlying
+/// allocation, which holds the header and the front redzone.
+///
+/// The redzone starts behind the metadata that `H` writes into freed blocks,
+/// so that freeing does not overwrite it.
+fn front_size<H: HeapAllocator>(layout: Layout) -> usize {
+    let metadata = if H::FREE_METADATA_SIZE > mem::size_of::<Header>() {
+        H::FREE_METADATA_SIZE
+    } else {
+        mem::size_of::<Header>()
+    };
+    align_up(metadata + REDZONE_SIZE, layout.align())
+}
+
+/// Returns the layout of the underlying allocation for `layout`.
+fn inner_layout<H: HeapAllocator>(layout: Layout) -> Option<Layout> {
+    let size = front_size::<H>(layout)
+        .checked_add(layout.size())?
+        .checked_add(REDZONE_SIZE)?;
+    let align = layout.align().max(mem::align_of::<Header>());
+    Layout::from_size_align(size, align).ok()
+}
+
+/// Returns the address of the first byte in `bytes` that is not `value`.
+fn find_mismatch(bytes: &[u8], value: u8) -> Option<*const u8> {
+    bytes.iter().find(|&&b| b != value).map(|b| b as *const u8)
+}
+
+unsafe impl<H: HeapAllocator> GlobalAlloc for DebugAllocator<H>
+where
+    Locked<H>: GlobalAlloc,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(inner_layout) = inner_layout::<H>(layout) else {
+            return ptr::null_mut();
+        };
+        let base = unsafe { self.inner.alloc(inner_layout) };
+        if base.is_null() {
+            return base;
+        }
+
+        self.usage.lock().record_alloc(layout);
+        let front = front_size::<H>(layout);
+        unsafe {
+            base.cast::<Header>().write(Header {
+                size: layout.size(),
+                align: layout.align(),
+            });
+            let header_end = base.add(mem::size_of::<Header>());
+            let ptr = base.add(front);
+            ptr::write_bytes(header_end, CANARY, front - mem::size_of::<Header>());
+            ptr::write_bytes(ptr, ALLOC_POISON, layout.size());
+            ptr::write_bytes(ptr.add(layout.size()), CANARY, REDZONE_SIZE);
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let inner_layout = inner_layout::<H>(layout).expect("invalid layout");
+        let front = front_size::<H>(layout);
+        let (front_redzone, back_redzone) = unsafe {
+            (
+                slice::from_raw_parts(ptr.sub(REDZONE_SIZE), REDZONE_SIZE),
+                slice::from_raw_parts(ptr.add(layout.size()), REDZONE_SIZE),
+            )
+        };
+
+        // freeing poisons the redzones, while the allocator's own metadata
+        // only overwrites the bytes in front of the redzone
+        if find_mismatch(front_redzone, FREE_POISON).is_none() {
+            panic!("heap-debug: double free of {:p} with {:?}", ptr, layout);
+        }
+        if let Some(addr) = find_mismatch(front_redzone, CANARY) {
+            panic!(
+                "heap-debug: redzone before {:p} ({:?}) overwritten at {:p}",
+                ptr, layout, addr
+            );
+        }
+        if let Some(addr) = find_mismatch(back_redzone, CANARY) {
+            panic!(
+                "heap-debug: redzone after {:p} ({:?}) overwritten at {:p}",
+                ptr, layout, addr
+            );
+        }
+
+        let base = unsafe { ptr.sub(front) };
+        let header = unsafe { base.cast::<Header>().read() };
+        if header.size != layout.size() || header.align != layout.align() {
+            panic!(
+                "heap-debug: {:p} freed with {:?}, but allocated with size {} and align {}",
+                ptr, layout, header.size, header.align
+            );
+        }
+
+        self.usage.lock().record_dealloc(layout);
+        unsafe {
+            ptr::write_bytes(base, FREE_POISON, inner_layout.size());
+            self.inner.dealloc(base, inner_layout);
+        }
+    }
+}
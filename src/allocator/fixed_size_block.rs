@@ -1,20 +1,19 @@
 use core::{
     alloc::{GlobalAlloc, Layout},
     mem,
-    ptr::{self, NonNull},
 };
 
-use super::Locked;
-
-struct ListNode {
-    next: Option<&'static mut ListNode>,
-}
+use super::{
+    AllocatorStats, HeapAllocator, HeapStats, Locked, Usage,
+    linked_list::LinkedListAllocator,
+    slab::{FreeObject, Geometry, SlabList},
+};
 
 /// The block sizes to use.
 ///
 /// The sizes must each be power of 2 because they are also used as
 /// the block alignment (alignments must be always powers of 2).
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 /// Choose an appropriate block size for the given layout.
 ///
@@ -24,21 +23,81 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
+/// Returns the geometry of the slabs for blocks of `BLOCK_SIZES[index]`.
+fn geometry(index: usize) -> Geometry {
+    Geometry::new(BLOCK_SIZES[index], BLOCK_SIZES[index])
+}
+
+/// An allocator that carves blocks of fixed sizes from slabs, which are
+/// allocated from a fallback linked list heap and given back to it once all
+/// their blocks are free again.
+///
+/// Allocations larger than the largest block size are served by the fallback
+/// heap directly.
 pub struct FixedSizeBlockAllocator {
-    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
-    fallback_allocator: linked_list_allocator::Heap,
+    /// For every block size, the slabs that have at least one free block.
+    slab_lists: [SlabList; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+    usage: Usage,
 }
 
+// the slabs are only accessed through the allocator
+unsafe impl Send for FixedSizeBlockAllocator {}
+
 impl FixedSizeBlockAllocator {
     /// Creates an empty FixedSizeBlockAllocator.
     #[allow(clippy::new_without_default)]
     pub const fn new() -> Self {
-        const EMPTY: Option<&'static mut ListNode> = None;
         FixedSizeBlockAllocator {
-            list_heads: [EMPTY; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            slab_lists: [const { SlabList::new() }; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+            usage: Usage::new(),
+        }
+    }
+
+    /// Takes a block of `BLOCK_SIZES[index]` from a slab, allocating a new
+    /// slab from the fallback heap if no slab of that size has free blocks.
+    fn alloc_block(&mut self, index: usize) -> *mut u8 {
+        let block = self.slab_lists[index].alloc();
+        if !block.is_null() {
+            return block;
+        }
+
+        let geometry = geometry(index);
+        let slab = self.fallback_allocator.allocate(geometry.slab_layout());
+        if slab.is_null() {
+            return slab;
+        }
+        unsafe { self.slab_lists[index].add_slab(slab, geometry) };
+        self.slab_lists[index].alloc()
+    }
+
+    /// Puts a block of `BLOCK_SIZES[index]` back into its slab and gives the
+    /// slab back to the fallback heap if all its blocks are free.
+    ///
+    /// # Safety
+    /// The block must have been allocated by `alloc_block` with the same
+    /// `index`.
+    unsafe fn dealloc_block(&mut self, ptr: *mut u8, index: usize) {
+        let geometry = geometry(index);
+        if let Some(slab) = unsafe { self.slab_lists[index].free(ptr, geometry) } {
+            unsafe {
+                self.fallback_allocator
+                    .deallocate(slab, geometry.slab_layout())
+            };
         }
     }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    /// Freed blocks are linked through a `FreeObject`, larger allocations are
+    /// freed to the fallback heap.
+    const FREE_METADATA_SIZE: usize =
+        if mem::size_of::<FreeObject>() > LinkedListAllocator::FREE_METADATA_SIZE {
+            mem::size_of::<FreeObject>()
+        } else {
+            LinkedListAllocator::FREE_METADATA_SIZE
+        };
 
     /// Initialize the allocator with the given heap bounds.
     ///
@@ -46,67 +105,137 @@ impl FixedSizeBlockAllocator {
     /// This function is unsafe because the caller must guarantee that the given
     /// heap bounds are valid and that the heap is unused. This method must be
     /// called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         unsafe {
             self.fallback_allocator.init(heap_start, heap_size);
         }
     }
-
-    /// Allocates using the fallback allocator.
-    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
-        }
-    }
 }
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                match allocator.list_heads[index].take() {
-                    Some(node) => {
-                        allocator.list_heads[index] = node.next.take();
-                        node as *mut ListNode as *mut u8
-                    }
-                    None => {
-                        // no block exists in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are a power of 2
-                        let block_align = block_size;
-                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                        allocator.fallback_alloc(layout)
-                    }
-                }
-            }
-            None => allocator.fallback_alloc(layout),
+        let ptr = match list_index(&layout) {
+            Some(index) => allocator.alloc_block(index),
+            None => allocator.fallback_allocator.allocate(layout),
+        };
+        if !ptr.is_null() {
+            allocator.usage.record_alloc(layout);
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.usage.record_dealloc(layout);
         match list_index(&layout) {
-            Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                // verify that block has size and alignment required for storing node
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                unsafe {
-                    new_node_ptr.write(new_node);
-                    allocator.list_heads[index] = Some(&mut *new_node_ptr);
-                }
-            }
-            None => {
-                let ptr = NonNull::new(ptr).unwrap();
-                unsafe {
-                    allocator.fallback_allocator.deallocate(ptr, layout);
-                }
-            }
+            Some(index) => unsafe { allocator.dealloc_block(ptr, index) },
+            None => unsafe { allocator.fallback_allocator.deallocate(ptr, layout) },
         }
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let mut allocator = self.lock();
+        let in_place = match (list_index(&layout), list_index(&new_layout)) {
+            // the block is large enough already
+            (Some(index), Some(new_index)) => index == new_index,
+            (None, None) => unsafe {
+                allocator
+                    .fallback_allocator
+                    .resize_in_place(ptr, layout, new_size)
+            },
+            _ => false,
+        };
+        if in_place {
+            allocator.usage.record_realloc(layout, new_layout);
+            return ptr;
+        }
+
+        drop(allocator);
+        unsafe { super::realloc_by_copy(self, ptr, layout, new_size) }
+    }
+}
+
+impl AllocatorStats for FixedSizeBlockAllocator {
+    fn stats(&self) -> HeapStats {
+        let free_blocks = core::array::from_fn(|index| self.slab_lists[index].free_objects());
+
+        let fallback = self.fallback_allocator.stats();
+        HeapStats {
+            total_size: fallback.total_size,
+            free_blocks,
+            free_bytes: fallback.free_bytes,
+            largest_free_block: fallback.largest_free_block,
+            ..self.usage.stats()
+        }
+    }
+}
+
+#[test_case]
+fn free_slabs_return_to_fallback_heap() {
+    const HEAP_SIZE: usize = 64 * 1024;
+
+    static mut HEAP: [u64; HEAP_SIZE / 8] = [0; HEAP_SIZE / 8];
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(&raw mut HEAP as usize, HEAP_SIZE) };
+
+    // fill the whole heap with small blocks
+    let small = Layout::new::<u64>();
+    let mut last = core::ptr::null_mut::<u8>();
+    loop {
+        let ptr = unsafe { allocator.alloc(small) };
+        if ptr.is_null() {
+            break;
+        }
+        // chain the blocks together to free them later
+        unsafe { ptr.cast::<*mut u8>().write(last) };
+        last = ptr;
+    }
+    assert!(!last.is_null());
+
+    let large = Layout::from_size_align(HEAP_SIZE / 2, 8).unwrap();
+    assert!(unsafe { allocator.alloc(large) }.is_null());
+
+    while !last.is_null() {
+        let next = unsafe { last.cast::<*mut u8>().read() };
+        unsafe { allocator.dealloc(last, small) };
+        last = next;
+    }
+    assert_eq!(allocator.lock().stats().free_blocks, [0; BLOCK_SIZES.len()]);
+
+    let ptr = unsafe { allocator.alloc(large) };
+    assert!(!ptr.is_null());
+    unsafe { allocator.dealloc(ptr, large) };
+}
+
+#[test_case]
+fn realloc_keeps_blocks_in_place() {
+    const HEAP_SIZE: usize = 64 * 1024;
+
+    static mut HEAP: [u64; HEAP_SIZE / 8] = [0; HEAP_SIZE / 8];
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(&raw mut HEAP as usize, HEAP_SIZE) };
+
+    // a block stays in place as long as the new size fits the same block size
+    let small = Layout::from_size_align(40, 8).unwrap();
+    let block = unsafe { allocator.alloc(small) };
+    unsafe { block.write_bytes(0x42, 40) };
+    assert_eq!(unsafe { allocator.realloc(block, small, 64) }, block);
+    let grown = Layout::from_size_align(64, 8).unwrap();
+    let moved = unsafe { allocator.realloc(block, grown, 65) };
+    assert_ne!(moved, block);
+    assert_eq!(unsafe { moved.add(39).read() }, 0x42);
+
+    // large allocations grow into the free memory after them
+    let large = Layout::from_size_align(4096, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(large) };
+    assert_eq!(unsafe { allocator.realloc(ptr, large, 8192) }, ptr);
+
+    // the moved block and the large allocation
+    let stats = allocator.lock().stats();
+    assert_eq!(stats.allocations, 2);
+    assert_eq!(stats.used_bytes, 65 + 8192);
 }
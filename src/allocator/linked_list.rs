@@ -6,7 +6,7 @@ use core::{
 
 use crate::allocator::align_up;
 
-use super::Locked;
+use super::{AllocatorStats, HeapAllocator, HeapStats, Locked, Usage};
 
 pub struct ListNode {
     size: usize,
@@ -29,6 +29,9 @@ impl ListNode {
 
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_start: usize,
+    heap_end: usize,
+    usage: Usage,
 }
 
 impl LinkedListAllocator {
@@ -37,34 +40,47 @@ impl LinkedListAllocator {
     pub const fn new() -> Self {
         LinkedListAllocator {
             head: ListNode::new(0),
+            heap_start: 0,
+            heap_end: 0,
+            usage: Usage::new(),
         }
     }
 
-    /// Initialize the allocator with the given heap bounds.
-    ///
-    /// # Safety
-    /// This function is unsafe because the caller must guarantee that the given
-    /// heap bounds are valid and that the heap is unused. This method must be
-    /// called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        unsafe {
-            self.add_free_region(heap_start, heap_size);
-        }
-    }
-
-    /// Adds the given memory region to the front of the list.
+    /// Adds the given memory region to the list, which is kept sorted by
+    /// address. The region is merged with the free regions directly before
+    /// and after it.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // ensure that the freed region is capable of holding ListNode
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        // create a new list node and append it at the start of the list
+        // find the last region that starts before the freed one
+        let mut current = &mut self.head;
+        while let Some(next) = &current.next
+            && next.start_addr() < addr
+        {
+            current = current.next.as_mut().unwrap();
+        }
+
+        // merge with the following region
+        let (size, next) = match current.next.take() {
+            Some(next) if addr + size == next.start_addr() => (size + next.size, next.next.take()),
+            next => (size, next),
+        };
+
+        // merge with the preceding region, the head is not part of the heap
+        if current.size > 0 && current.end_addr() == addr {
+            current.size += size;
+            current.next = next;
+            return;
+        }
+
         let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
+        node.next = next;
         let node_ptr = addr as *mut ListNode;
         unsafe {
             node_ptr.write(node);
-            self.head.next = Some(&mut *node_ptr)
+            current.next = Some(&mut *node_ptr)
         }
     }
 
@@ -113,10 +129,159 @@ impl LinkedListAllocator {
             return Err(());
         }
 
+        let padding = alloc_start - region.start_addr();
+        if padding > 0 && padding < mem::size_of::<ListNode>() {
+            // same for the alignment padding in front of the allocation
+            return Err(());
+        }
+
         // region suitable for allocation
         Ok(alloc_start)
     }
 
+    /// Grows the heap so that a region of the given size and alignment fits
+    /// into the new part of it.
+    ///
+    /// Returns whether the heap could be grown.
+    fn grow(&mut self, size: usize, align: usize) -> bool {
+        let Ok(layout) = Layout::from_size_align(size, align) else {
+            return false;
+        };
+        let Some(grown) = super::grow_heap(self.heap_end, layout) else {
+            return false;
+        };
+        unsafe {
+            self.add_free_region(self.heap_end, grown);
+        }
+        self.heap_end += grown;
+        true
+    }
+
+    /// Allocates memory for the given layout, growing the heap if no free
+    /// region is large enough.
+    ///
+    /// Returns a null pointer if the allocation fails.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        // perform layout adjustments
+        let (size, align) = Self::size_align(layout);
+
+        let mut found = self.find_region(size, align);
+        if found.is_none() && self.grow(size, align) {
+            found = self.find_region(size, align);
+        }
+
+        let Some((region, alloc_start)) = found else {
+            return null_mut();
+        };
+        let alloc_end = alloc_start.checked_add(size).expect("overflow");
+        let excess_size = region.end_addr() - alloc_end;
+        let padding = alloc_start - region.start_addr();
+        if excess_size > 0 {
+            unsafe {
+                self.add_free_region(alloc_end, excess_size);
+            }
+        }
+        if padding > 0 {
+            unsafe {
+                self.add_free_region(region.start_addr(), padding);
+            }
+        }
+        self.usage.record_alloc(layout);
+        alloc_start as *mut u8
+    }
+
+    /// Frees the memory at `ptr`.
+    ///
+    /// # Safety
+    /// The memory must have been allocated by `allocate` with the same layout.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        // perform layout adjustments
+        let (size, _) = Self::size_align(layout);
+
+        self.usage.record_dealloc(layout);
+        unsafe { self.add_free_region(ptr as usize, size) }
+    }
+
+    /// Tries to resize the allocation at `ptr` to `new_size` bytes without
+    /// moving it, by freeing its end or taking the free region directly after
+    /// it.
+    ///
+    /// Returns whether the allocation was resized.
+    ///
+    /// # Safety
+    /// The memory must have been allocated by `allocate` with `layout`.
+    pub unsafe fn resize_in_place(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> bool {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return false;
+        };
+        let (size, _) = Self::size_align(layout);
+        let (new_size, _) = Self::size_align(new_layout);
+        let end = ptr as usize + size;
+
+        if new_size < size {
+            let rest = size - new_size;
+            if rest < mem::size_of::<ListNode>() {
+                return false;
+            }
+            unsafe { self.add_free_region(ptr as usize + new_size, rest) };
+        } else if new_size > size {
+            let missing = new_size - size;
+            let mut region = self.take_region_at(end, missing);
+            if region.is_none() && self.free_end(end) == self.heap_end && self.grow(missing, 1) {
+                region = self.take_region_at(end, missing);
+            }
+            let Some(region_size) = region else {
+                return false;
+            };
+            if region_size > missing {
+                unsafe { self.add_free_region(end + missing, region_size - missing) };
+            }
+        }
+
+        self.usage.record_realloc(layout, new_layout);
+        true
+    }
+
+    /// Removes the free region starting at `addr` from the list, if it has at
+    /// least `min_size` bytes and the rest of it can hold a `ListNode`.
+    ///
+    /// Returns the size of the region.
+    fn take_region_at(&mut self, addr: usize, min_size: usize) -> Option<usize> {
+        let mut current = &mut self.head;
+        while let Some(next) = &current.next
+            && next.start_addr() < addr
+        {
+            current = current.next.as_mut().unwrap();
+        }
+
+        let region = current.next.as_mut()?;
+        let rest = region.size.checked_sub(min_size)?;
+        if region.start_addr() != addr || (rest > 0 && rest < mem::size_of::<ListNode>()) {
+            return None;
+        }
+        let size = region.size;
+        current.next = region.next.take();
+        Some(size)
+    }
+
+    /// Returns the end of the free region starting at `addr`, or `addr` if
+    /// there is none.
+    fn free_end(&self, addr: usize) -> usize {
+        let mut current = &self.head;
+        while let Some(region) = &current.next {
+            if region.start_addr() == addr {
+                return region.end_addr();
+            }
+            current = region;
+        }
+        addr
+    }
+
     /// Adjust the given layout so that the resulting allocated memory
     /// region is also capable of storing a `ListNode`.
     ///
@@ -131,30 +296,129 @@ impl LinkedListAllocator {
     }
 }
 
+impl HeapAllocator for LinkedListAllocator {
+    const FREE_METADATA_SIZE: usize = mem::size_of::<ListNode>();
+
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        unsafe {
+            self.add_free_region(heap_start, heap_size);
+        }
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // perform layout adjustments
-        let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
-
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                unsafe {
-                    allocator.add_free_region(alloc_end, excess_size);
-                }
-            }
-            alloc_start as *mut u8
-        } else {
-            null_mut()
-        }
+        self.lock().allocate(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        // perform layout adjustments
-        let (size, _) = LinkedListAllocator::size_align(layout);
+        unsafe { self.lock().deallocate(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if unsafe { self.lock().resize_in_place(ptr, layout, new_size) } {
+            return ptr;
+        }
+        unsafe { super::realloc_by_copy(self, ptr, layout, new_size) }
+    }
+}
+
+impl AllocatorStats for LinkedListAllocator {
+    fn stats(&self) -> HeapStats {
+        let mut free_bytes = 0;
+        let mut largest_free_block = 0;
+        let mut current = &self.head;
+        while let Some(region) = &current.next {
+            free_bytes += region.size;
+            largest_free_block = largest_free_block.max(region.size);
+            current = region;
+        }
+
+        HeapStats {
+            total_size: self.heap_end - self.heap_start,
+            free_bytes,
+            largest_free_block,
+            ..self.usage.stats()
+        }
+    }
+}
+
+#[test_case]
+fn freed_regions_are_merged() {
+    const HEAP_SIZE: usize = 16 * 1024;
+    const BLOCK_SIZE: usize = 64;
+
+    static mut HEAP: [u64; HEAP_SIZE / 8] = [0; HEAP_SIZE / 8];
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(&raw mut HEAP as usize, HEAP_SIZE) };
+
+    let block = Layout::from_size_align(BLOCK_SIZE, 8).unwrap();
+    let mut blocks = [null_mut(); HEAP_SIZE / BLOCK_SIZE];
+    for ptr in blocks.iter_mut() {
+        *ptr = unsafe { allocator.alloc(block) };
+        assert!(!ptr.is_null());
+    }
+    assert_eq!(allocator.lock().stats().free_bytes, 0);
+
+    // free every other block first, so that no two free blocks are adjacent
+    for &ptr in blocks.iter().step_by(2) {
+        unsafe { allocator.dealloc(ptr, block) };
+    }
+    for &ptr in blocks.iter().skip(1).step_by(2) {
+        unsafe { allocator.dealloc(ptr, block) };
+    }
+
+    let whole_heap = Layout::from_size_align(HEAP_SIZE, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(whole_heap) };
+    assert_eq!(ptr, &raw mut HEAP as *mut u8);
+    unsafe { allocator.dealloc(ptr, whole_heap) };
+}
+
+#[test_case]
+fn realloc_resizes_in_place() {
+    const HEAP_SIZE: usize = 4096;
+
+    static mut HEAP: [u64; HEAP_SIZE / 8] = [0; HEAP_SIZE / 8];
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(&raw mut HEAP as usize, HEAP_SIZE) };
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    let second = unsafe { allocator.alloc(layout) };
+    unsafe { first.write_bytes(0x42, 64) };
+
+    // freeing the second allocation lets the first one grow into its memory
+    let grown_layout = Layout::from_size_align(128, 8).unwrap();
+    unsafe { allocator.dealloc(second, layout) };
+    let grown = unsafe { allocator.realloc(first, layout, 128) };
+    assert_eq!(grown, first);
+    assert_eq!(unsafe { first.add(63).read() }, 0x42);
+
+    let shrunk = unsafe { allocator.realloc(first, grown_layout, 32) };
+    assert_eq!(shrunk, first);
+    let stats = allocator.lock().stats();
+    assert_eq!(stats.free_bytes, HEAP_SIZE - 32);
+    assert_eq!(stats.largest_free_block, HEAP_SIZE - 32);
 
-        unsafe { self.lock().add_free_region(ptr as usize, size) }
+    // the following allocation forces a move
+    let second = unsafe { allocator.alloc(layout) };
+    let small = Layout::from_size_align(32, 8).unwrap();
+    let moved = unsafe { allocator.realloc(first, small, 128) };
+    assert_ne!(moved, first);
+    assert_eq!(unsafe { moved.add(31).read() }, 0x42);
+    unsafe {
+        allocator.dealloc(second, layout);
+        allocator.dealloc(moved, grown_layout);
     }
+    assert_eq!(allocator.lock().stats().free_bytes, HEAP_SIZE);
 }
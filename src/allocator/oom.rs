@@ -0,0 +1,169 @@
+use super::{HeapStats, fixed_size_block::BLOCK_SIZES, heap_stats};
+use crate::{eprintln, serial_println};
+use core::{
+    alloc::Layout,
+    fmt, mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// The maximum number of memory pressure callbacks that can be registered.
+const MAX_CALLBACKS: usize = 8;
+
+/// The minimum number of timer ticks between two failure reports, so that
+/// code that handles failed allocations, e.g. with `try_reserve`, does not
+/// flood the screen.
+const REPORT_INTERVAL: u64 = 100;
+
+/// A callback that is asked to free memory when an allocation of the given
+/// layout failed, e.g. by shrinking a cache.
+///
+/// Returns whether it freed any memory. Callbacks must not allocate.
+pub type PressureCallback = fn(Layout) -> bool;
+
+/// Identifies a registered memory pressure callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PressureCallbackId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureCallbackError {
+    /// All callback slots are in use.
+    TooManyCallbacks,
+    /// The callback was already unregistered.
+    NotFound,
+}
+
+/// The registered callbacks, in a fixed size table because they are called
+/// when the heap is exhausted.
+static CALLBACKS: Mutex<[Option<PressureCallback>; MAX_CALLBACKS]> =
+    Mutex::new([None; MAX_CALLBACKS]);
+
+/// Set while the callbacks run, so that a failing allocation inside a
+/// callback does not call them again.
+static RELIEVING: AtomicBool = AtomicBool::new(false);
+
+/// When a failure was last reported and how many failures were not reported
+/// since then.
+struct ReportLimit {
+    last: Option<u64>,
+    suppressed: u64,
+}
+
+static REPORT_LIMIT: Mutex<ReportLimit> = Mutex::new(ReportLimit {
+    last: None,
+    suppressed: 0,
+});
+
+/// Registers a callback that is called before an allocation fails.
+pub fn register_pressure_callback(
+    callback: PressureCallback,
+) -> Result<PressureCallbackId, PressureCallbackError> {
+    interrupts::without_interrupts(|| {
+        let mut callbacks = CALLBACKS.lock();
+        let slot = callbacks
+            .iter()
+            .position(Option::is_none)
+            .ok_or(PressureCallbackError::TooManyCallbacks)?;
+        callbacks[slot] = Some(callback);
+        Ok(PressureCallbackId(slot))
+    })
+}
+
+/// Unregisters a callback registered by `register_pressure_callback`.
+pub fn unregister_pressure_callback(id: PressureCallbackId) -> Result<(), PressureCallbackError> {
+    interrupts::without_interrupts(|| {
+        CALLBACKS.lock()[id.0]
+            .take()
+            .map(|_| ())
+            .ok_or(PressureCallbackError::NotFound)
+    })
+}
+
+/// Calls all registered callbacks after an allocation of `layout` failed.
+///
+/// Returns whether any of them freed memory, so that the allocation should be
+/// retried.
+pub(super) fn relieve_pressure(layout: Layout) -> bool {
+    if RELIEVING.swap(true, Ordering::Acquire) {
+        return false;
+    }
+    // copy the table, so that callbacks can free memory and (un)register
+    // callbacks without holding the lock
+    let callbacks = interrupts::without_interrupts(|| *CALLBACKS.lock());
+    let freed = callbacks
+        .iter()
+        .flatten()
+        .fold(false, |freed, callback| callback(layout) | freed);
+    RELIEVING.store(false, Ordering::Release);
+    freed
+}
+
+/// Prints the failed layout and the heap statistics to VGA and serial, unless
+/// a failure was reported less than `REPORT_INTERVAL` ticks ago.
+pub(super) fn report_failure(layout: Layout) {
+    let Some(suppressed) = take_report_slot() else {
+        return;
+    };
+    let report = Report {
+        layout,
+        stats: heap_stats(),
+        suppressed,
+    };
+    eprintln!("{}", report);
+    serial_println!("{}", report);
+}
+
+/// Returns the number of failures that were not reported since the last
+/// report if a failure may be reported now, or counts the failure as not
+/// reported.
+fn take_report_slot() -> Option<u64> {
+    interrupts::without_interrupts(|| {
+        let mut limit = REPORT_LIMIT.lock();
+        let now = crate::interrupts::ticks();
+        if limit.last.is_some_and(|last| now - last < REPORT_INTERVAL) {
+            limit.suppressed += 1;
+            return None;
+        }
+        limit.last = Some(now);
+        Some(mem::take(&mut limit.suppressed))
+    })
+}
+
+/// The diagnostics printed when an allocation fails.
+struct Report {
+    layout: Layout,
+    stats: HeapStats,
+    /// The failures since the previous report that were not reported.
+    suppressed: u64,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stats = &self.stats;
+        writeln!(
+            f,
+            "Allocation of {} bytes aligned to {} failed",
+            self.layout.size(),
+            self.layout.align()
+        )?;
+        writeln!(
+            f,
+            "Heap: {} bytes, {} used ({} peak) by {} allocations",
+            stats.total_size, stats.used_bytes, stats.peak_used_bytes, stats.allocations
+        )?;
+        writeln!(
+            f,
+            "Free: {} bytes, largest free block {} bytes",
+            stats.free_bytes, stats.largest_free_block
+        )?;
+        write!(f, "Free blocks:")?;
+        for (size, count) in BLOCK_SIZES.iter().zip(stats.free_blocks) {
+            write!(f, " {}B:{}", size, count)?;
+        }
+        if self.suppressed > 0 {
+            write!(f, "\n{} earlier failures not reported", self.suppressed)?;
+        }
+        Ok(())
+    }
+}
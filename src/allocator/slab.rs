@@ -0,0 +1,422 @@
+use super::{Locked, align_up};
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+use core::{
+    alloc::Layout,
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+
+/// The minimum size of a slab.
+const MIN_SLAB_SIZE: usize = 4096;
+
+/// A free object, linked into the free list of its slab.
+pub(super) struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// The header at the start of every slab.
+struct Slab {
+    free_list: *mut FreeObject,
+    /// The length of `free_list`.
+    free_objects: usize,
+    /// The neighbours in the list of slabs that have free objects.
+    prev: *mut Slab,
+    next: *mut Slab,
+}
+
+/// The sizes of the objects and slabs of a slab list.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Geometry {
+    object_size: usize,
+    slab_size: usize,
+    first_object_offset: usize,
+}
+
+impl Geometry {
+    /// Returns the geometry for objects of the given size and alignment.
+    pub(super) fn new(size: usize, align: usize) -> Self {
+        let align = align.max(mem::align_of::<FreeObject>());
+        let object_size = align_up(size.max(mem::size_of::<FreeObject>()), align);
+        // slabs are aligned to their size, so that the slab of an object can
+        // be found by rounding down the object's address
+        let slab_size = (object_size * 8).next_power_of_two().max(MIN_SLAB_SIZE);
+        Geometry {
+            object_size,
+            slab_size,
+            first_object_offset: align_up(mem::size_of::<Slab>(), align),
+        }
+    }
+
+    fn of<T>() -> Self {
+        Self::new(mem::size_of::<T>(), mem::align_of::<T>())
+    }
+
+    fn objects_per_slab(&self) -> usize {
+        (self.slab_size - self.first_object_offset) / self.object_size
+    }
+
+    /// Returns the layout the slabs must be allocated with.
+    pub(super) fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size, self.slab_size).unwrap()
+    }
+}
+
+/// The slabs of one geometry that have at least one free object.
+///
+/// The owner of the list allocates the slabs and gives them back once `free`
+/// returns them, so that the list can carve slabs from any heap.
+pub(super) struct SlabList {
+    partial_slabs: *mut Slab,
+}
+
+impl SlabList {
+    pub(super) const fn new() -> Self {
+        SlabList {
+            partial_slabs: ptr::null_mut(),
+        }
+    }
+
+    /// Takes a free object, or returns null if no slab has free objects.
+    pub(super) fn alloc(&mut self) -> *mut u8 {
+        let slab = self.partial_slabs;
+        if slab.is_null() {
+            return ptr::null_mut();
+        }
+
+        unsafe {
+            let object = (*slab).free_list;
+            (*slab).free_list = (*object).next;
+            (*slab).free_objects -= 1;
+            if (*slab).free_objects == 0 {
+                self.unlink_slab(slab);
+            }
+            object as *mut u8
+        }
+    }
+
+    /// Puts an object back into its slab.
+    ///
+    /// Returns the slab if all its objects are free. It is removed from the
+    /// list and must be given back to the heap it was allocated from.
+    ///
+    /// # Safety
+    /// The object must have been allocated by `alloc` from a slab of the same
+    /// geometry.
+    pub(super) unsafe fn free(&mut self, ptr: *mut u8, geometry: Geometry) -> Option<*mut u8> {
+        let slab = (ptr as usize & !(geometry.slab_size - 1)) as *mut Slab;
+        let object = ptr as *mut FreeObject;
+        unsafe {
+            object.write(FreeObject {
+                next: (*slab).free_list,
+            });
+            (*slab).free_list = object;
+            (*slab).free_objects += 1;
+
+            if (*slab).free_objects == 1 {
+                self.link_slab(slab);
+            }
+            if (*slab).free_objects == geometry.objects_per_slab() {
+                self.unlink_slab(slab);
+                return Some(slab as *mut u8);
+            }
+        }
+        None
+    }
+
+    /// Carves a new slab into free objects and adds it to the list.
+    ///
+    /// # Safety
+    /// The slab must be an unused allocation of `geometry.slab_layout()`.
+    pub(super) unsafe fn add_slab(&mut self, slab: *mut u8, geometry: Geometry) {
+        let slab = slab as *mut Slab;
+
+        // push the objects in reverse, so that they are handed out in order
+        let mut free_list = ptr::null_mut();
+        for offset in (geometry.first_object_offset..geometry.slab_size)
+            .step_by(geometry.object_size)
+            .take(geometry.objects_per_slab())
+            .rev()
+        {
+            let object = (slab as usize + offset) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free_list }) };
+            free_list = object;
+        }
+
+        unsafe {
+            slab.write(Slab {
+                free_list,
+                free_objects: geometry.objects_per_slab(),
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+            });
+            self.link_slab(slab);
+        }
+    }
+
+    /// Returns the number of free objects in all slabs of the list.
+    pub(super) fn free_objects(&self) -> usize {
+        let mut count = 0;
+        let mut slab = self.partial_slabs;
+        while !slab.is_null() {
+            unsafe {
+                count += (*slab).free_objects;
+                slab = (*slab).next;
+            }
+        }
+        count
+    }
+
+    /// Adds the slab to the front of the list.
+    unsafe fn link_slab(&mut self, slab: *mut Slab) {
+        let head = self.partial_slabs;
+        unsafe {
+            (*slab).prev = ptr::null_mut();
+            (*slab).next = head;
+            if !head.is_null() {
+                (*head).prev = slab;
+            }
+        }
+        self.partial_slabs = slab;
+    }
+
+    /// Removes the slab from the list.
+    unsafe fn unlink_slab(&mut self, slab: *mut Slab) {
+        unsafe {
+            let (prev, next) = ((*slab).prev, (*slab).next);
+            if prev.is_null() {
+                self.partial_slabs = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+    }
+}
+
+/// The untyped state of a cache.
+struct Cache {
+    slab_list: SlabList,
+    slabs: usize,
+    objects: usize,
+    peak_objects: usize,
+}
+
+// the slabs are only accessed through the cache
+unsafe impl Send for Cache {}
+
+impl Cache {
+    /// Takes a free object, or returns null if no slab has free objects.
+    fn alloc(&mut self) -> *mut u8 {
+        let object = self.slab_list.alloc();
+        if !object.is_null() {
+            self.count_alloc();
+        }
+        object
+    }
+
+    /// Adds a new slab and takes a free object.
+    ///
+    /// # Safety
+    /// The slab must be a valid allocation of `geometry.slab_layout()`.
+    unsafe fn alloc_with_slab(&mut self, slab: *mut u8, geometry: Geometry) -> *mut u8 {
+        unsafe { self.slab_list.add_slab(slab, geometry) };
+        self.slabs += 1;
+        self.count_alloc();
+        self.slab_list.alloc()
+    }
+
+    fn count_alloc(&mut self) {
+        self.objects += 1;
+        self.peak_objects = self.peak_objects.max(self.objects);
+    }
+
+    /// Puts an object back into its slab and returns the slab if all its
+    /// objects are free, which the caller gives back to the global allocator.
+    ///
+    /// # Safety
+    /// The object must have been allocated by the cache with the same geometry.
+    unsafe fn free(&mut self, ptr: *mut u8, geometry: Geometry) -> Option<*mut u8> {
+        self.objects -= 1;
+        let slab = unsafe { self.slab_list.free(ptr, geometry) };
+        if slab.is_some() {
+            self.slabs -= 1;
+        }
+        slab
+    }
+}
+
+/// The usage of a slab cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStats {
+    pub name: &'static str,
+    /// The size of an object including padding.
+    pub object_size: usize,
+    pub slab_size: usize,
+    /// The number of slabs the cache holds.
+    pub slabs: usize,
+    /// The number of live objects.
+    pub objects: usize,
+    /// The largest value `objects` ever had.
+    pub peak_objects: usize,
+    /// The number of free objects in the slabs of the cache.
+    pub free_objects: usize,
+}
+
+/// A named cache for objects of type `T`.
+///
+/// Objects are carved from slabs that belong to this cache only, so
+/// allocating an object takes the lock of the cache instead of the global
+/// allocator's. Only the slabs themselves are allocated from the global
+/// allocator, and they are given back as soon as all their objects are free.
+/// The lock of the cache is not held while the global allocator runs, as its
+/// pressure callbacks or allocation tracking may use caches themselves.
+///
+/// With `heap-debug`, the header and redzones the debug allocator puts around
+/// every slab are padded to the alignment of the slab, so a 4 KiB slab takes
+/// more than 8 KiB of heap memory.
+///
+/// Caches are meant to be statics of the subsystem that uses them.
+pub struct SlabCache<T> {
+    name: &'static str,
+    constructor: Option<fn() -> T>,
+    cache: Locked<Cache>,
+}
+
+impl<T> SlabCache<T> {
+    /// Creates an empty cache.
+    pub const fn new(name: &'static str) -> Self {
+        SlabCache {
+            name,
+            constructor: None,
+            cache: Locked::new(Cache {
+                slab_list: SlabList::new(),
+                slabs: 0,
+                objects: 0,
+                peak_objects: 0,
+            }),
+        }
+    }
+
+    /// Creates an empty cache whose objects are initialized by `constructor`
+    /// in `alloc_constructed`.
+    pub const fn with_constructor(name: &'static str, constructor: fn() -> T) -> Self {
+        SlabCache {
+            constructor: Some(constructor),
+            ..Self::new(name)
+        }
+    }
+
+    /// Moves `value` into an object of the cache.
+    ///
+    /// Calls `handle_alloc_error` if no slab could be allocated, like
+    /// `Box::new`.
+    pub fn alloc(&self, value: T) -> SlabBox<'_, T> {
+        let geometry = Geometry::of::<T>();
+        let mut object = self.cache.lock().alloc();
+        if object.is_null() {
+            let slab = unsafe { alloc(geometry.slab_layout()) };
+            if slab.is_null() {
+                handle_alloc_error(geometry.slab_layout());
+            }
+            object = unsafe { self.cache.lock().alloc_with_slab(slab, geometry) };
+        }
+
+        let ptr = unsafe { NonNull::new_unchecked(object as *mut T) };
+        unsafe { ptr.write(value) };
+        SlabBox { ptr, cache: self }
+    }
+
+    /// Allocates an object initialized by the constructor of the cache.
+    ///
+    /// Panics if the cache was created without a constructor.
+    pub fn alloc_constructed(&self) -> SlabBox<'_, T> {
+        let constructor = self
+            .constructor
+            .unwrap_or_else(|| panic!("slab cache {} has no constructor", self.name));
+        self.alloc(constructor())
+    }
+
+    /// Returns the usage of the cache.
+    pub fn stats(&self) -> SlabStats {
+        let geometry = Geometry::of::<T>();
+        let cache = self.cache.lock();
+        SlabStats {
+            name: self.name,
+            object_size: geometry.object_size,
+            slab_size: geometry.slab_size,
+            slabs: cache.slabs,
+            objects: cache.objects,
+            peak_objects: cache.peak_objects,
+            free_objects: cache.slabs * geometry.objects_per_slab() - cache.objects,
+        }
+    }
+}
+
+/// An object allocated from a `SlabCache`, which is dropped and given back to
+/// the cache when the box is dropped.
+pub struct SlabBox<'a, T> {
+    ptr: NonNull<T>,
+    cache: &'a SlabCache<T>,
+}
+
+impl<'a, T> SlabBox<'a, T> {
+    /// Consumes the box without dropping or freeing the object, e.g. to
+    /// manage its lifetime by hand.
+    pub fn into_raw(self) -> NonNull<T> {
+        let ptr = self.ptr;
+        mem::forget(self);
+        ptr
+    }
+
+    /// Takes back ownership of an object that was returned by `into_raw`.
+    ///
+    /// # Safety
+    /// The object must have been allocated from `cache` and must not be owned
+    /// by another box.
+    pub unsafe fn from_raw(ptr: NonNull<T>, cache: &'a SlabCache<T>) -> Self {
+        SlabBox { ptr, cache }
+    }
+}
+
+// the box owns its object like a `Box`
+unsafe impl<T: Send> Send for SlabBox<'_, T> {}
+unsafe impl<T: Sync> Sync for SlabBox<'_, T> {}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        let geometry = Geometry::of::<T>();
+        unsafe { ptr::drop_in_place(self.ptr.as_ptr()) };
+        let slab = unsafe {
+            self.cache
+                .cache
+                .lock()
+                .free(self.ptr.as_ptr() as *mut u8, geometry)
+        };
+        if let Some(slab) = slab {
+            unsafe { dealloc(slab, geometry.slab_layout()) };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
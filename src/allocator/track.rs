@@ -0,0 +1,271 @@
+use crate::{memory, serial_println};
+use core::{alloc::Layout, arch::asm};
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    instructions::interrupts,
+    structures::paging::{OffsetPageTable, Translate},
+};
+
+/// The maximum number of live allocations that can be tracked.
+const MAX_TRACKED: usize = 4096;
+/// The number of return addresses recorded for every allocation.
+const TRACE_DEPTH: usize = 4;
+/// The number of frames between `record_alloc` and the code that called into
+/// the global allocator, which are skipped.
+const SKIP_FRAMES: usize = 2;
+/// The maximum distance between the stack pointer and the last frame that is
+/// walked, to stop at a corrupted frame pointer.
+const MAX_STACK_DEPTH: usize = 1024 * 1024;
+
+/// A live allocation.
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    addr: usize,
+    layout: Layout,
+    /// The return addresses of the callers of the global allocator, innermost
+    /// first. Unused entries are zero.
+    callers: [usize; TRACE_DEPTH],
+    tag: Option<&'static str>,
+    /// The sequence number of the allocation.
+    id: u64,
+}
+
+impl Record {
+    /// Returns whether two records belong to the same group in a dump.
+    fn same_group(&self, other: &Record) -> bool {
+        self.callers == other.callers && self.tag == other.tag
+    }
+}
+
+/// The live allocations in an open addressing hash table keyed by address.
+///
+/// This is a static table, because it can not use the heap it tracks.
+struct Table {
+    slots: [Option<Record>; MAX_TRACKED],
+    next_id: u64,
+    /// The number of allocations that did not fit into the table.
+    dropped: usize,
+}
+
+impl Table {
+    /// Returns the preferred slot of an address.
+    fn home(addr: usize) -> usize {
+        (addr >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15) % MAX_TRACKED
+    }
+
+    fn insert(&mut self, record: Record) {
+        let home = Self::home(record.addr);
+        let slot = (0..MAX_TRACKED)
+            .map(|i| (home + i) % MAX_TRACKED)
+            .find(|&i| self.slots[i].is_none());
+        match slot {
+            Some(i) => self.slots[i] = Some(record),
+            None => self.dropped += 1,
+        }
+    }
+
+    fn remove(&mut self, addr: usize) {
+        let home = Self::home(addr);
+        let found = (0..MAX_TRACKED)
+            .map(|i| (home + i) % MAX_TRACKED)
+            .take_while(|&i| self.slots[i].is_some())
+            .find(|&i| self.slots[i].is_some_and(|r| r.addr == addr));
+        let Some(mut hole) = found else {
+            // the allocation did not fit into the table
+            self.dropped = self.dropped.saturating_sub(1);
+            return;
+        };
+        self.slots[hole] = None;
+
+        // move following records of the same probe sequence into the hole
+        let mut i = hole;
+        loop {
+            i = (i + 1) % MAX_TRACKED;
+            let Some(record) = self.slots[i] else {
+                break;
+            };
+            let home = Self::home(record.addr);
+            let stays = if hole <= i {
+                hole < home && home <= i
+            } else {
+                hole < home || home <= i
+            };
+            if !stays {
+                self.slots[hole] = Some(record);
+                self.slots[i] = None;
+                hole = i;
+            }
+        }
+    }
+
+    fn records(&self) -> impl Iterator<Item = &Record> {
+        self.slots.iter().flatten()
+    }
+}
+
+static TABLE: Mutex<Table> = Mutex::new(Table {
+    slots: [None; MAX_TRACKED],
+    next_id: 0,
+    dropped: 0,
+});
+
+/// The tag of new allocations, set by `tagged`.
+static TAG: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Returns the return addresses of the callers of the global allocator by
+/// following the frame pointers.
+///
+/// The kernel keeps frame pointers only when built with
+/// `RUSTFLAGS="-Cforce-frame-pointers=yes"`, otherwise `rbp` may hold any
+/// value and the callers are garbage. Every frame is checked to lie above the
+/// stack pointer in mapped memory before it is read, so the walk never faults.
+/// No callers are recorded while the page table is locked.
+#[inline(always)]
+fn callers() -> [usize; TRACE_DEPTH] {
+    let mut callers = [0; TRACE_DEPTH];
+    let (mut rbp, rsp): (usize, usize);
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+        asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack));
+    }
+
+    let Some(mapper) = memory::MAPPER.try_lock() else {
+        return callers;
+    };
+    let Some(mapper) = mapper.as_ref() else {
+        return callers;
+    };
+    for i in 0..SKIP_FRAMES + TRACE_DEPTH {
+        if !is_frame(mapper, rbp, rsp) {
+            break;
+        }
+        let (next, return_addr) = unsafe {
+            let frame = rbp as *const usize;
+            (frame.read(), frame.add(1).read())
+        };
+        if i >= SKIP_FRAMES {
+            callers[i - SKIP_FRAMES] = return_addr;
+        }
+        // the frames of callers are above the frame of the callee
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+    callers
+}
+
+/// Returns whether `rbp` may point to a stack frame of the current stack, an
+/// aligned and mapped saved frame pointer and return address above `rsp`.
+fn is_frame(mapper: &OffsetPageTable, rbp: usize, rsp: usize) -> bool {
+    let mapped = |addr: usize| {
+        VirtAddr::try_new(addr as u64).is_ok_and(|addr| mapper.translate_addr(addr).is_some())
+    };
+    rbp.is_multiple_of(8)
+        && rbp >= rsp
+        && rbp - rsp <= MAX_STACK_DEPTH
+        && mapped(rbp)
+        // the return address may lie on the next page
+        && rbp.checked_add(8).is_some_and(mapped)
+}
+
+/// Records a new allocation, called by the global allocator.
+#[inline(never)]
+pub(super) fn record_alloc(ptr: *mut u8, layout: Layout) {
+    let callers = callers();
+    interrupts::without_interrupts(|| {
+        let tag = *TAG.lock();
+        let mut table = TABLE.lock();
+        let id = table.next_id;
+        table.next_id += 1;
+        table.insert(Record {
+            addr: ptr as usize,
+            layout,
+            callers,
+            tag,
+            id,
+        });
+    });
+}
+
+/// Removes a freed allocation, called by the global allocator.
+pub(super) fn record_dealloc(ptr: *mut u8) {
+    interrupts::without_interrupts(|| TABLE.lock().remove(ptr as usize));
+}
+
+/// Runs `f` and tags all allocations it makes with `tag`.
+pub fn tagged<R>(tag: &'static str, f: impl FnOnce() -> R) -> R {
+    let previous = interrupts::without_interrupts(|| TAG.lock().replace(tag));
+    let result = f();
+    interrupts::without_interrupts(|| *TAG.lock() = previous);
+    result
+}
+
+/// Returns the number of live allocations.
+pub fn live_allocations() -> usize {
+    interrupts::without_interrupts(|| {
+        let table = TABLE.lock();
+        table.records().count() + table.dropped
+    })
+}
+
+/// Returns the number of live allocations with the given tag.
+pub fn live_allocations_tagged(tag: &str) -> usize {
+    interrupts::without_interrupts(|| {
+        let table = TABLE.lock();
+        table.records().filter(|r| r.tag == Some(tag)).count()
+    })
+}
+
+/// Prints all live allocations to serial, grouped by their callers and tag.
+pub fn dump() {
+    interrupts::without_interrupts(|| print_groups(&TABLE.lock(), 0));
+}
+
+/// Runs `f` and panics if any allocation made by it is still live afterwards.
+///
+/// The leaked allocations are printed to serial like in `dump`.
+pub fn assert_no_leaks<R>(f: impl FnOnce() -> R) -> R {
+    let first_id = interrupts::without_interrupts(|| TABLE.lock().next_id);
+    let result = f();
+    let leaks = interrupts::without_interrupts(|| {
+        let table = TABLE.lock();
+        let leaks = table.records().filter(|r| r.id >= first_id).count();
+        if leaks > 0 {
+            print_groups(&table, first_id);
+        }
+        leaks
+    });
+    assert_eq!(leaks, 0, "{} allocations leaked", leaks);
+    result
+}
+
+/// Prints the records with an id of at least `first_id`, grouped by their
+/// callers and tag.
+fn print_groups(table: &Table, first_id: u64) {
+    let records = || table.records().filter(|r| r.id >= first_id);
+
+    serial_println!("Live allocations:");
+    for (i, record) in records().enumerate() {
+        // print every group once, at its first record
+        if records().take(i).any(|r| r.same_group(record)) {
+            continue;
+        }
+        let (count, bytes) = records()
+            .filter(|r| r.same_group(record))
+            .fold((0, 0), |(count, bytes), r| {
+                (count + 1, bytes + r.layout.size())
+            });
+        serial_println!(
+            "  {} allocations, {} bytes, tag {}, callers {:#x?}",
+            count,
+            bytes,
+            record.tag.unwrap_or("-"),
+            record.callers
+        );
+    }
+    if table.dropped > 0 {
+        serial_println!("  {} allocations not tracked, table full", table.dropped);
+    }
+}
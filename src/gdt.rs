@@ -1,7 +1,13 @@
 #![allow(clippy::let_and_return)]
+use crate::memory::{
+    stack::{self, KernelStack},
+    vmm::VmmError,
+};
 use lazy_static::lazy_static;
+use spin::Mutex;
 use x86_64::{
     VirtAddr,
+    instructions::interrupts,
     structures::{
         gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
         tss::TaskStateSegment,
@@ -9,21 +15,26 @@ use x86_64::{
 };
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// The number of pages of each guard-paged interrupt stack.
+pub const IST_STACK_PAGES: u64 = 5;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE;
-
-            stack_end
-        };
-        tss
-    };
+/// The task state segment. Its interrupt stack table is only modified by
+/// `init` and `init_ist_stacks`, with interrupts disabled.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// The guard-paged interrupt stacks installed by `init_ist_stacks`.
+static IST_STACKS: Mutex<[Option<KernelStack>; 7]> = Mutex::new([None; 7]);
+
+/// Returns the stack used by the double fault handler until the memory
+/// management is initialized and `init_ist_stacks` replaces it.
+fn boot_double_fault_stack() -> VirtAddr {
+    const STACK_SIZE: usize = 4096 * 5;
+    static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+    let stack_start = VirtAddr::from_ptr(&raw const STACK);
+    let stack_end = stack_start + STACK_SIZE;
+
+    stack_end
 }
 
 struct Selectors {
@@ -35,7 +46,9 @@ lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        // the TSS is a static, so the pointer stays valid
+        let tss = unsafe { Descriptor::tss_segment_unchecked(&raw const TSS) };
+        let tss_selector = gdt.add_entry(tss);
         (
             gdt,
             Selectors {
@@ -50,6 +63,7 @@ pub fn init() {
     use x86_64::instructions::segmentation::{CS, Segment};
     use x86_64::instructions::tables::load_tss;
 
+    unsafe { set_ist_entry(DOUBLE_FAULT_IST_INDEX, boot_double_fault_stack()) };
     GDT.0.load();
 
     unsafe {
@@ -57,3 +71,31 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Points the interrupt stack table entry `index` to `stack_top`.
+///
+/// # Safety
+/// The stack must be valid and not in use, and interrupts must be disabled.
+unsafe fn set_ist_entry(index: u16, stack_top: VirtAddr) {
+    unsafe { TSS.interrupt_stack_table[index as usize] = stack_top };
+}
+
+/// Replaces the static interrupt stacks with stacks that have a guard page
+/// below them, so that overflowing them faults instead of corrupting memory.
+///
+/// Requires the memory management to be initialized by
+/// `memory::init_global`.
+pub fn init_ist_stacks() -> Result<(), VmmError> {
+    let stack = stack::alloc_stack(IST_STACK_PAGES)?;
+    interrupts::without_interrupts(|| {
+        IST_STACKS.lock()[DOUBLE_FAULT_IST_INDEX as usize] = Some(stack);
+        unsafe { set_ist_entry(DOUBLE_FAULT_IST_INDEX, stack.top()) };
+    });
+    Ok(())
+}
+
+/// Returns the guard-paged stack of the given interrupt stack table entry,
+/// if `init_ist_stacks` installed one.
+pub fn ist_stack(index: u16) -> Option<KernelStack> {
+    IST_STACKS.lock().get(index as usize).copied().flatten()
+}
@@ -1,26 +1,23 @@
 use crate::vga_buffer::STDOUT;
-use crate::{eprintln, hlt_loop};
-use crate::gdt;
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
-use x86_64::structures::idt::PageFaultErrorCode;
 use x86_64::{
     instructions::{interrupts, port::Port},
-    structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+    structures::idt::InterruptDescriptorTable,
 };
 
+pub mod apic;
+mod exceptions;
+pub mod irq;
+pub mod madt;
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        exceptions::install(&mut idt);
+        irq::install(&mut idt);
+        idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(apic::spurious_interrupt_handler);
         idt
     };
 }
@@ -29,32 +26,62 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    eprintln!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+/// Sets up the 8259 PICs and registers the handlers of the timer and the
+/// keyboard, unless an earlier call registered them already.
+pub fn init_irqs() {
+    irq::init_pics();
+    let handlers: [(u8, irq::IrqHandler); 2] = [
+        (irq::TIMER, timer_interrupt_handler),
+        (irq::KEYBOARD, keyboard_interrupt_handler),
+    ];
+    for (line, handler) in handlers {
+        match irq::register(line, handler) {
+            Ok(_) | Err(irq::IrqError::AlreadyRegistered) => {}
+            Err(err) => panic!("could not register the handler of IRQ {}: {:?}", line, err),
+        }
+    }
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    eprintln!("EXCEPTION: DOUBLE_FAULT\n{:#?}", stack_frame);
-    panic!("Double exception")
+/// The controllers that deliver hardware interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+    /// The legacy 8259 PICs, used from boot on.
+    Pic,
+    /// The Local and I/O APICs, used once `apic::init` succeeded.
+    Apic,
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-
-    eprintln!("EXCEPTION: PAGE FAULT");
-    eprintln!("Accessed Address: {:?}", Cr2::read());
-    eprintln!("Error Code: {:?}", error_code);
-    eprintln!("{:#?}", stack_frame);
-    hlt_loop();
+/// Selects the controller that delivers hardware interrupts: the APICs if the
+/// machine has them, or the 8259 PICs with the `pic` feature.
+///
+/// Requires that `memory::init_global` was called before. On error the PICs
+/// stay in use.
+pub fn init_controller() -> Result<Controller, apic::ApicError> {
+    #[cfg(not(feature = "pic"))]
+    apic::init()?;
+    Ok(controller())
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// Returns the controller that currently delivers hardware interrupts.
+pub fn controller() -> Controller {
+    if apic::is_active() {
+        Controller::Apic
+    } else {
+        Controller::Pic
+    }
+}
+
+/// The number of timer interrupts since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+fn timer_interrupt_handler(_irq: u8) {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+
     let col = 79;
     let row = 0;
 
@@ -74,24 +101,12 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
             .write_byte_at(byte, row, col)
             .expect("Indexes out of bounds");
     });
-
-    // Signal end of interrupt handling
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+fn keyboard_interrupt_handler(_irq: u8) {
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
-
-    // Signal end of interrupt handling
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
 }
 
 /*
@@ -116,20 +131,3 @@ pub static PICS: spin::Mutex<ChainedPics> =
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
-
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard,
-}
-
-impl InterruptIndex {
-    fn as_u8(self) -> u8 {
-        self as u8
-    }
-
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
-    }
-}
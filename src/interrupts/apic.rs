@@ -0,0 +1,416 @@
+use super::{
+    PICS, irq,
+    madt::{ISA_IRQS, IsaIrq, MAX_IO_APICS, Madt},
+};
+use crate::memory::{
+    self,
+    mmio::{MmioRegion, UNCACHED},
+    vmm::VmmError,
+};
+use core::{
+    arch::x86_64::__cpuid,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Mutex;
+use x86_64::{
+    PhysAddr,
+    instructions::{interrupts, port::Port},
+    registers::model_specific::Msr,
+    structures::idt::InterruptStackFrame,
+};
+
+/// The vector of spurious interrupts of the Local APIC. Its low four bits
+/// must be set on older processors.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// The frequency of the Local APIC timer interrupts.
+pub const TIMER_HZ: u64 = 100;
+
+#[derive(Debug)]
+pub enum ApicError {
+    /// The processor has no Local APIC.
+    Unsupported,
+    /// `memory::init_global` was not called, so neither the ACPI tables nor
+    /// the registers can be mapped.
+    MemoryNotInitialized,
+    /// The MADT lists no I/O APIC.
+    NoIoApic,
+    /// The registers of an APIC could not be mapped.
+    Mmio(VmmError),
+}
+
+/// The Local APIC of the boot processor, set up by `init`.
+static LOCAL_APIC: Mutex<Option<LocalApic>> = Mutex::new(None);
+
+/// The I/O APICs, set up by `init`.
+static IO_APICS: Mutex<[Option<IoApic>; MAX_IO_APICS]> = Mutex::new([const { None }; MAX_IO_APICS]);
+
+/// The I/O APIC input of every ISA IRQ, `None` for IRQs whose input is used by
+/// another IRQ.
+static ROUTES: Mutex<[Option<IsaIrq>; ISA_IRQS]> = Mutex::new([None; ISA_IRQS]);
+
+/// Whether interrupts are delivered through the APICs instead of the PICs.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether the processor has a Local APIC.
+pub fn is_supported() -> bool {
+    const CPUID_APIC: u32 = 1 << 9;
+    __cpuid(1).edx & CPUID_APIC != 0
+}
+
+/// Returns whether `init` switched interrupt delivery to the APICs.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Switches interrupt delivery from the 8259 PICs to the APICs.
+///
+/// The controllers are read from the ACPI MADT, or QEMU's defaults are used if
+/// there is none. The 8259 PICs are masked and the ISA IRQs are routed through
+/// the I/O APIC to the same vectors, unmasked if they have handlers. The timer
+/// interrupt comes from the Local APIC timer at `TIMER_HZ` instead of the PIT.
+///
+/// On error nothing was changed, so the PICs stay in use.
+pub fn init() -> Result<(), ApicError> {
+    if !is_supported() {
+        return Err(ApicError::Unsupported);
+    }
+    let physical_memory_offset =
+        memory::physical_memory_offset().ok_or(ApicError::MemoryNotInitialized)?;
+    let madt = Madt::read(physical_memory_offset).unwrap_or_else(Madt::qemu_defaults);
+
+    // map all registers before touching any controller
+    let mut local_apic = LocalApic::map(&madt)?;
+    let mut io_apics = [const { None }; MAX_IO_APICS];
+    for (slot, info) in io_apics.iter_mut().zip(madt.io_apics) {
+        if let Some(info) = info {
+            *slot = Some(IoApic::map(info.address, info.gsi_base)?);
+        }
+    }
+    if io_apics.iter().all(Option::is_none) {
+        return Err(ApicError::NoIoApic);
+    }
+
+    interrupts::without_interrupts(|| {
+        if madt.has_8259 {
+            unsafe { PICS.lock().disable() };
+        }
+
+        local_apic.enable();
+        for io_apic in io_apics.iter_mut().flatten() {
+            io_apic.mask_all();
+        }
+
+        let destination = local_apic.id();
+        let routes = isa_routes(&madt);
+        for (irq, route) in (0..).zip(routes) {
+            if let Some(route) = route {
+                // the PIT stays masked, the Local APIC timer replaces it
+                let masked = irq == irq::TIMER || !irq::is_handled(irq);
+                program(&mut io_apics, route, irq::vector(irq), destination, masked);
+            }
+        }
+
+        let ticks_per_interrupt = local_apic.calibrate_timer() * 1000 / (CALIBRATION_MS * TIMER_HZ);
+        local_apic.start_timer(irq::vector(irq::TIMER), ticks_per_interrupt as u32);
+
+        *LOCAL_APIC.lock() = Some(local_apic);
+        *IO_APICS.lock() = io_apics;
+        *ROUTES.lock() = routes;
+        ACTIVE.store(true, Ordering::Release);
+    });
+    Ok(())
+}
+
+/// Signals the end of an interrupt to the Local APIC.
+pub(super) fn end_of_interrupt() {
+    if let Some(local_apic) = LOCAL_APIC.lock().as_mut() {
+        local_apic.end_of_interrupt();
+    }
+}
+
+/// Returns whether the Local APIC delivered an interrupt on the vector that
+/// waits for its end of interrupt.
+///
+/// Interrupts that did not come through the Local APIC, like software
+/// interrupts or those of the 8259 PICs, are never in service.
+pub(super) fn is_in_service(vector: u8) -> bool {
+    LOCAL_APIC
+        .lock()
+        .as_ref()
+        .is_some_and(|local_apic| local_apic.is_in_service(vector))
+}
+
+/// Masks or unmasks an ISA IRQ in the I/O APIC.
+pub(super) fn set_masked(irq: u8, masked: bool) {
+    // the Local APIC timer replaces the PIT
+    if irq == irq::TIMER {
+        return;
+    }
+    let Some(route) = ROUTES.lock().get(usize::from(irq)).copied().flatten() else {
+        return;
+    };
+    let mut io_apics = IO_APICS.lock();
+    if let Some(io_apic) = io_apics
+        .iter_mut()
+        .flatten()
+        .find(|io_apic| io_apic.handles(route.gsi))
+    {
+        io_apic.set_masked(route.gsi - io_apic.gsi_base, masked);
+    }
+}
+
+/// How an ISA IRQ is delivered by the I/O APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redirection {
+    pub vector: u8,
+    pub masked: bool,
+}
+
+/// Returns how an ISA IRQ is delivered, or `None` if the APICs are not active
+/// or the IRQ has no input of its own.
+pub fn redirection(irq: u8) -> Option<Redirection> {
+    interrupts::without_interrupts(|| {
+        let route = ROUTES.lock().get(usize::from(irq)).copied().flatten()?;
+        let mut io_apics = IO_APICS.lock();
+        let io_apic = io_apics
+            .iter_mut()
+            .flatten()
+            .find(|io_apic| io_apic.handles(route.gsi))?;
+        let entry = io_apic.redirection(route.gsi - io_apic.gsi_base);
+        Some(Redirection {
+            vector: entry as u8,
+            masked: entry & REDIRECTION_MASKED != 0,
+        })
+    })
+}
+
+/// Returns the I/O APIC input of every ISA IRQ, leaving out IRQs whose input
+/// another IRQ was moved to, like IRQ 2 whose input is used by the PIT.
+fn isa_routes(madt: &Madt) -> [Option<IsaIrq>; ISA_IRQS] {
+    core::array::from_fn(|irq| {
+        let route = madt.isa_irqs[irq];
+        let taken = (0..).zip(madt.isa_irqs).any(|(other, other_route)| {
+            other != irq as u32 && other_route.gsi == route.gsi && other_route.gsi != other
+        });
+        (!taken).then_some(route)
+    })
+}
+
+/// Programs the redirection entry of an ISA IRQ in the I/O APIC that handles
+/// its global system interrupt.
+fn program(
+    io_apics: &mut [Option<IoApic>; MAX_IO_APICS],
+    irq: IsaIrq,
+    vector: u8,
+    destination: u8,
+    masked: bool,
+) {
+    let Some(io_apic) = io_apics
+        .iter_mut()
+        .flatten()
+        .find(|io_apic| io_apic.handles(irq.gsi))
+    else {
+        return;
+    };
+
+    let mut entry = u64::from(vector) | u64::from(destination) << 56;
+    if irq.active_low {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if irq.level_triggered {
+        entry |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+    if masked {
+        entry |= REDIRECTION_MASKED;
+    }
+    io_apic.set_redirection(irq.gsi - io_apic.gsi_base, entry);
+}
+
+/// Handles the spurious interrupts of the Local APIC, which need no EOI.
+pub(super) extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+}
+
+/// The MSR holding the physical address and the enable flag of the Local APIC.
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// The registers of the Local APIC.
+const LAPIC_ID: u64 = 0x20;
+const LAPIC_TASK_PRIORITY: u64 = 0x80;
+const LAPIC_EOI: u64 = 0xb0;
+const LAPIC_SPURIOUS: u64 = 0xf0;
+/// The in-service register, 8 registers of 32 vectors each, 0x10 apart.
+const LAPIC_IN_SERVICE: u64 = 0x100;
+const LAPIC_LVT_TIMER: u64 = 0x320;
+const LAPIC_TIMER_INITIAL_COUNT: u64 = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: u64 = 0x390;
+const LAPIC_TIMER_DIVIDE: u64 = 0x3e0;
+const LAPIC_REGISTERS_SIZE: u64 = 0x400;
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// The time over which the Local APIC timer is measured against the PIT.
+const CALIBRATION_MS: u64 = 10;
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+#[derive(Debug)]
+struct LocalApic {
+    registers: MmioRegion,
+}
+
+impl LocalApic {
+    fn map(madt: &Madt) -> Result<Self, ApicError> {
+        let registers = unsafe { MmioRegion::map(madt.local_apic, LAPIC_REGISTERS_SIZE, UNCACHED) }
+            .map_err(ApicError::Mmio)?;
+        Ok(LocalApic { registers })
+    }
+
+    fn id(&self) -> u8 {
+        (self.registers.read_u32(LAPIC_ID) >> 24) as u8
+    }
+
+    /// Enables the Local APIC and lets it accept interrupts of all priorities.
+    fn enable(&mut self) {
+        let mut apic_base = Msr::new(IA32_APIC_BASE);
+        unsafe {
+            let value = apic_base.read();
+            apic_base.write(value | APIC_BASE_ENABLE);
+        }
+        self.registers.write_u32(LAPIC_TASK_PRIORITY, 0);
+        self.registers.write_u32(
+            LAPIC_SPURIOUS,
+            SPURIOUS_APIC_ENABLE | u32::from(SPURIOUS_VECTOR),
+        );
+    }
+
+    fn end_of_interrupt(&mut self) {
+        self.registers.write_u32(LAPIC_EOI, 0);
+    }
+
+    fn is_in_service(&self, vector: u8) -> bool {
+        let register = LAPIC_IN_SERVICE + u64::from(vector / 32) * 0x10;
+        self.registers.read_u32(register) & (1 << (vector % 32)) != 0
+    }
+
+    /// Returns the number of timer ticks in `CALIBRATION_MS`, measured with
+    /// channel 2 of the PIT.
+    fn calibrate_timer(&mut self) -> u64 {
+        let mut gate = Port::<u8>::new(0x61);
+        let mut command = Port::<u8>::new(0x43);
+        let mut channel_2 = Port::<u8>::new(0x42);
+        let count = (PIT_FREQUENCY * CALIBRATION_MS / 1000) as u16;
+
+        self.registers.write_u32(LAPIC_LVT_TIMER, LVT_MASKED);
+        self.registers
+            .write_u32(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        unsafe {
+            // stop channel 2 and keep the speaker off while loading the count
+            let control = gate.read() & !0b11;
+            gate.write(control);
+            // channel 2, low and high byte, one-shot
+            command.write(0b1011_0000);
+            channel_2.write(count as u8);
+            channel_2.write((count >> 8) as u8);
+
+            gate.write(control | 1);
+            self.registers
+                .write_u32(LAPIC_TIMER_INITIAL_COUNT, u32::MAX);
+            // the output of channel 2 goes high when the count ran out
+            while gate.read() & 0x20 == 0 {}
+        }
+        let remaining = self.registers.read_u32(LAPIC_TIMER_CURRENT_COUNT);
+        self.registers.write_u32(LAPIC_TIMER_INITIAL_COUNT, 0);
+        u64::from(u32::MAX - remaining)
+    }
+
+    /// Starts the timer in periodic mode with the divider set by
+    /// `calibrate_timer`.
+    fn start_timer(&mut self, vector: u8, ticks_per_interrupt: u32) {
+        self.registers
+            .write_u32(LAPIC_LVT_TIMER, LVT_TIMER_PERIODIC | u32::from(vector));
+        self.registers
+            .write_u32(LAPIC_TIMER_INITIAL_COUNT, ticks_per_interrupt.max(1));
+    }
+}
+
+/// The registers of the I/O APIC, which are accessed indirectly through a
+/// register select and a data window.
+const IOAPIC_REGISTER_SELECT: u64 = 0x00;
+const IOAPIC_WINDOW: u64 = 0x10;
+const IOAPIC_REGISTERS_SIZE: u64 = 0x20;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+#[derive(Debug)]
+struct IoApic {
+    registers: MmioRegion,
+    /// The global system interrupt of the first pin.
+    gsi_base: u32,
+    pins: u32,
+}
+
+impl IoApic {
+    fn map(address: PhysAddr, gsi_base: u32) -> Result<Self, ApicError> {
+        let registers = unsafe { MmioRegion::map(address, IOAPIC_REGISTERS_SIZE, UNCACHED) }
+            .map_err(ApicError::Mmio)?;
+        let mut io_apic = IoApic {
+            registers,
+            gsi_base,
+            pins: 0,
+        };
+        io_apic.pins = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xff) + 1;
+        Ok(io_apic)
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        self.registers.write_u32(IOAPIC_REGISTER_SELECT, register);
+        self.registers.read_u32(IOAPIC_WINDOW)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write_u32(IOAPIC_REGISTER_SELECT, register);
+        self.registers.write_u32(IOAPIC_WINDOW, value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.pins).contains(&gsi)
+    }
+
+    fn set_redirection(&mut self, pin: u32, entry: u64) {
+        let register = IOAPIC_REDIRECTION_TABLE + pin * 2;
+        // mask the pin while the entry is half written
+        self.write(register, REDIRECTION_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    fn redirection(&mut self, pin: u32) -> u64 {
+        let register = IOAPIC_REDIRECTION_TABLE + pin * 2;
+        u64::from(self.read(register + 1)) << 32 | u64::from(self.read(register))
+    }
+
+    fn set_masked(&mut self, pin: u32, masked: bool) {
+        let register = IOAPIC_REDIRECTION_TABLE + pin * 2;
+        let entry = self.read(register);
+        let entry = if masked {
+            entry | REDIRECTION_MASKED as u32
+        } else {
+            entry & !(REDIRECTION_MASKED as u32)
+        };
+        self.write(register, entry);
+    }
+
+    fn mask_all(&mut self) {
+        for pin in 0..self.pins {
+            self.set_redirection(pin, REDIRECTION_MASKED);
+        }
+    }
+}
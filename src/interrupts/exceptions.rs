@@ -0,0 +1,504 @@
+use crate::{eprintln, gdt, memory, serial_println};
+use core::{arch::naked_asm, fmt};
+use x86_64::{
+    VirtAddr,
+    registers::control::{Cr0, Cr2, Cr3, Cr4},
+    structures::idt::{
+        DescriptorTable, Entry, EntryOptions, InterruptDescriptorTable, InterruptStackFrame,
+        PageFaultErrorCode, SelectorErrorCode,
+    },
+};
+
+/// An architectural exception.
+#[derive(Debug, Clone, Copy)]
+struct Exception {
+    vector: u8,
+    mnemonic: &'static str,
+    name: &'static str,
+}
+
+impl Exception {
+    const fn new(vector: u8, mnemonic: &'static str, name: &'static str) -> Self {
+        Exception {
+            vector,
+            mnemonic,
+            name,
+        }
+    }
+}
+
+const DIVIDE_ERROR: Exception = Exception::new(0, "DE", "DIVIDE ERROR");
+const DEBUG: Exception = Exception::new(1, "DB", "DEBUG");
+const NON_MASKABLE_INTERRUPT: Exception = Exception::new(2, "NMI", "NON-MASKABLE INTERRUPT");
+const BREAKPOINT: Exception = Exception::new(3, "BP", "BREAKPOINT");
+const OVERFLOW: Exception = Exception::new(4, "OF", "OVERFLOW");
+const BOUND_RANGE_EXCEEDED: Exception = Exception::new(5, "BR", "BOUND RANGE EXCEEDED");
+const INVALID_OPCODE: Exception = Exception::new(6, "UD", "INVALID OPCODE");
+const DEVICE_NOT_AVAILABLE: Exception = Exception::new(7, "NM", "DEVICE NOT AVAILABLE");
+const DOUBLE_FAULT: Exception = Exception::new(8, "DF", "DOUBLE FAULT");
+const INVALID_TSS: Exception = Exception::new(10, "TS", "INVALID TSS");
+const SEGMENT_NOT_PRESENT: Exception = Exception::new(11, "NP", "SEGMENT NOT PRESENT");
+const STACK_SEGMENT_FAULT: Exception = Exception::new(12, "SS", "STACK-SEGMENT FAULT");
+const GENERAL_PROTECTION_FAULT: Exception = Exception::new(13, "GP", "GENERAL PROTECTION FAULT");
+const PAGE_FAULT: Exception = Exception::new(14, "PF", "PAGE FAULT");
+const X87_FLOATING_POINT: Exception = Exception::new(16, "MF", "X87 FLOATING-POINT ERROR");
+const ALIGNMENT_CHECK: Exception = Exception::new(17, "AC", "ALIGNMENT CHECK");
+const MACHINE_CHECK: Exception = Exception::new(18, "MC", "MACHINE CHECK");
+const SIMD_FLOATING_POINT: Exception = Exception::new(19, "XM", "SIMD FLOATING-POINT EXCEPTION");
+const VIRTUALIZATION: Exception = Exception::new(20, "VE", "VIRTUALIZATION EXCEPTION");
+const CONTROL_PROTECTION: Exception = Exception::new(21, "CP", "CONTROL PROTECTION EXCEPTION");
+const HYPERVISOR_INJECTION: Exception = Exception::new(28, "HV", "HYPERVISOR INJECTION EXCEPTION");
+const VMM_COMMUNICATION: Exception = Exception::new(29, "VC", "VMM COMMUNICATION EXCEPTION");
+const SECURITY: Exception = Exception::new(30, "SX", "SECURITY EXCEPTION");
+
+/// The error code pushed by an exception, decoded according to its type.
+#[derive(Debug, Clone, Copy)]
+enum ErrorCode {
+    None,
+    /// The selector of the segment or gate that caused the exception.
+    Selector(u64),
+    Page(PageFaultErrorCode),
+    ControlProtection(u64),
+    Raw(u64),
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorCode::None => write!(f, "none"),
+            ErrorCode::Selector(0) => write!(f, "0 (no selector)"),
+            ErrorCode::Selector(code) => {
+                let selector = SelectorErrorCode::new_truncate(code);
+                let table = match selector.descriptor_table() {
+                    DescriptorTable::Gdt => "GDT",
+                    DescriptorTable::Idt => "IDT",
+                    DescriptorTable::Ldt => "LDT",
+                };
+                write!(f, "{:#x} ({} index {}", code, table, selector.index())?;
+                if selector.external() {
+                    write!(f, ", external event")?;
+                }
+                write!(f, ")")
+            }
+            ErrorCode::Page(code) => write!(f, "{:?}", code),
+            ErrorCode::ControlProtection(code) => {
+                let cause = match code & 0x7fff {
+                    1 => "near RET",
+                    2 => "far RET or IRET",
+                    3 => "missing ENDBRANCH",
+                    4 => "RSTORSSP",
+                    5 => "SETSSBSY",
+                    _ => "unknown",
+                };
+                write!(f, "{:#x} ({})", code, cause)
+            }
+            ErrorCode::Raw(code) => write!(f, "{:#x}", code),
+        }
+    }
+}
+
+/// The general-purpose registers at the time of an exception, in the order
+/// the entry stubs push them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Registers {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // three registers per line, to fit on the screen
+        writeln!(
+            f,
+            "RAX: {:#018x}  RBX: {:#018x}  RCX: {:#018x}",
+            self.rax, self.rbx, self.rcx
+        )?;
+        writeln!(
+            f,
+            "RDX: {:#018x}  RSI: {:#018x}  RDI: {:#018x}",
+            self.rdx, self.rsi, self.rdi
+        )?;
+        writeln!(
+            f,
+            "RBP: {:#018x}  R8:  {:#018x}  R9:  {:#018x}",
+            self.rbp, self.r8, self.r9
+        )?;
+        writeln!(
+            f,
+            "R10: {:#018x}  R11: {:#018x}  R12: {:#018x}",
+            self.r10, self.r11, self.r12
+        )?;
+        write!(
+            f,
+            "R13: {:#018x}  R14: {:#018x}  R15: {:#018x}",
+            self.r13, self.r14, self.r15
+        )
+    }
+}
+
+/// Saves the general-purpose registers as `Registers` on the stack.
+macro_rules! push_registers {
+    () => {
+        "push rax; push rbx; push rcx; push rdx; push rsi; push rdi; push rbp; \
+         push r8; push r9; push r10; push r11; push r12; push r13; push r14; push r15"
+    };
+}
+
+/// Restores the general-purpose registers saved by `push_registers`.
+macro_rules! pop_registers {
+    () => {
+        "pop r15; pop r14; pop r13; pop r12; pop r11; pop r10; pop r9; pop r8; \
+         pop rbp; pop rdi; pop rsi; pop rdx; pop rcx; pop rbx; pop rax"
+    };
+}
+
+/// Defines the entry stub of an exception, which saves the general-purpose
+/// registers, calls `$handler` and returns from the exception if the handler
+/// returns. The handler takes the stack frame, the error code and the saved
+/// registers. With `error_code`, the stub passes on and removes the error code
+/// the processor pushed, otherwise the handler gets 0.
+///
+/// The stubs replace the `x86-interrupt` ABI, which saves the registers but
+/// does not let the handler read their values.
+macro_rules! entry {
+    ($vis:vis $entry:ident, $handler:path) => {
+        #[unsafe(naked)]
+        $vis extern "C" fn $entry() {
+            // the processor aligned the stack to 16 bytes before pushing the 5
+            // words of the stack frame, the 15 registers keep it aligned
+            naked_asm!(
+                push_registers!(),
+                "cld",
+                "lea rdi, [rsp + 15 * 8]",
+                "xor esi, esi",
+                "mov rdx, rsp",
+                "call {handler}",
+                pop_registers!(),
+                "iretq",
+                handler = sym $handler,
+            )
+        }
+    };
+    ($vis:vis $entry:ident, $handler:path, error_code) => {
+        #[unsafe(naked)]
+        $vis extern "C" fn $entry() {
+            naked_asm!(
+                push_registers!(),
+                "cld",
+                "lea rdi, [rsp + 16 * 8]",
+                "mov rsi, [rsp + 15 * 8]",
+                "mov rdx, rsp",
+                // the error code left the stack 8 bytes off alignment
+                "sub rsp, 8",
+                "call {handler}",
+                "add rsp, 8",
+                pop_registers!(),
+                "add rsp, 8",
+                "iretq",
+                handler = sym $handler,
+            )
+        }
+    };
+}
+
+/// The diagnostics printed for an exception.
+struct Report<'a> {
+    exception: Exception,
+    error_code: ErrorCode,
+    stack_frame: &'a InterruptStackFrame,
+    registers: &'a Registers,
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Exception {
+            vector,
+            mnemonic,
+            name,
+        } = self.exception;
+        writeln!(f, "EXCEPTION: {} (#{}, vector {})", name, mnemonic, vector)?;
+        if !matches!(self.error_code, ErrorCode::None) {
+            writeln!(f, "Error code: {}", self.error_code)?;
+        }
+
+        let frame = &**self.stack_frame;
+        writeln!(
+            f,
+            "RIP: {:#018x}  CS: {:#06x}  RFLAGS: {:#018x}",
+            frame.instruction_pointer.as_u64(),
+            frame.code_segment,
+            frame.cpu_flags
+        )?;
+        writeln!(
+            f,
+            "RSP: {:#018x}  SS: {:#06x}",
+            frame.stack_pointer.as_u64(),
+            frame.stack_segment
+        )?;
+        writeln!(f, "{}", self.registers)?;
+        write!(
+            f,
+            "CR0: {:#x}  CR2: {:#x}  CR3: {:#x}  CR4: {:#x}",
+            Cr0::read_raw(),
+            Cr2::read_raw(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
+}
+
+/// Prints the diagnostics of an exception to VGA and serial.
+fn report(
+    exception: Exception,
+    error_code: ErrorCode,
+    stack_frame: &InterruptStackFrame,
+    registers: &Registers,
+) {
+    let report = Report {
+        exception,
+        error_code,
+        stack_frame,
+        registers,
+    };
+    eprintln!("{}", report);
+    serial_println!("{}", report);
+}
+
+/// Reports an exception the kernel can not recover from and panics.
+fn fatal(
+    exception: Exception,
+    error_code: ErrorCode,
+    stack_frame: &InterruptStackFrame,
+    registers: &Registers,
+) -> ! {
+    report(exception, error_code, stack_frame, registers);
+    panic!("EXCEPTION: {}", exception.name)
+}
+
+/// Defines a module `$handler` with the entry stub of an exception that is
+/// reported and panics. With a decoder for the error code, the processor
+/// pushes an error code for the exception.
+macro_rules! fatal_handler {
+    ($handler:ident, $exception:expr) => {
+        mod $handler {
+            use super::*;
+
+            extern "C" fn handler(
+                stack_frame: &InterruptStackFrame,
+                _error_code: u64,
+                registers: &Registers,
+            ) {
+                fatal($exception, ErrorCode::None, stack_frame, registers)
+            }
+
+            entry!(pub(super) entry, handler);
+        }
+    };
+    ($handler:ident, $exception:expr, $decode:expr) => {
+        mod $handler {
+            use super::*;
+
+            extern "C" fn handler(
+                stack_frame: &InterruptStackFrame,
+                error_code: u64,
+                registers: &Registers,
+            ) {
+                fatal($exception, $decode(error_code), stack_frame, registers)
+            }
+
+            entry!(pub(super) entry, handler, error_code);
+        }
+    };
+}
+
+fatal_handler!(divide_error_handler, DIVIDE_ERROR);
+fatal_handler!(overflow_handler, OVERFLOW);
+fatal_handler!(bound_range_exceeded_handler, BOUND_RANGE_EXCEEDED);
+fatal_handler!(invalid_opcode_handler, INVALID_OPCODE);
+fatal_handler!(device_not_available_handler, DEVICE_NOT_AVAILABLE);
+fatal_handler!(double_fault_handler, DOUBLE_FAULT, ErrorCode::Raw);
+fatal_handler!(invalid_tss_handler, INVALID_TSS, ErrorCode::Selector);
+fatal_handler!(
+    segment_not_present_handler,
+    SEGMENT_NOT_PRESENT,
+    ErrorCode::Selector
+);
+fatal_handler!(
+    stack_segment_fault_handler,
+    STACK_SEGMENT_FAULT,
+    ErrorCode::Selector
+);
+fatal_handler!(
+    general_protection_fault_handler,
+    GENERAL_PROTECTION_FAULT,
+    ErrorCode::Selector
+);
+fatal_handler!(x87_floating_point_handler, X87_FLOATING_POINT);
+fatal_handler!(alignment_check_handler, ALIGNMENT_CHECK, ErrorCode::Raw);
+fatal_handler!(machine_check_handler, MACHINE_CHECK);
+fatal_handler!(simd_floating_point_handler, SIMD_FLOATING_POINT);
+fatal_handler!(virtualization_handler, VIRTUALIZATION);
+fatal_handler!(
+    control_protection_handler,
+    CONTROL_PROTECTION,
+    ErrorCode::ControlProtection
+);
+fatal_handler!(hypervisor_injection_handler, HYPERVISOR_INJECTION);
+fatal_handler!(vmm_communication_handler, VMM_COMMUNICATION, ErrorCode::Raw);
+fatal_handler!(security_handler, SECURITY, ErrorCode::Raw);
+
+entry!(debug_entry, debug_handler);
+entry!(non_maskable_interrupt_entry, non_maskable_interrupt_handler);
+entry!(breakpoint_entry, breakpoint_handler);
+entry!(page_fault_entry, page_fault_handler, error_code);
+
+/// Points the IDT entry to an entry stub.
+fn set_entry<F>(idt_entry: &mut Entry<F>, entry: extern "C" fn()) -> &mut EntryOptions {
+    // the stub returns with `iretq` like an `x86-interrupt` handler
+    unsafe { idt_entry.set_handler_addr(VirtAddr::from_ptr(entry as *const ())) }
+}
+
+/// Installs handlers for all architectural exceptions.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    set_entry(&mut idt.divide_error, divide_error_handler::entry);
+    set_entry(&mut idt.debug, debug_entry);
+    set_entry(
+        &mut idt.non_maskable_interrupt,
+        non_maskable_interrupt_entry,
+    );
+    set_entry(&mut idt.breakpoint, breakpoint_entry);
+    set_entry(&mut idt.overflow, overflow_handler::entry);
+    set_entry(
+        &mut idt.bound_range_exceeded,
+        bound_range_exceeded_handler::entry,
+    );
+    set_entry(&mut idt.invalid_opcode, invalid_opcode_handler::entry);
+    set_entry(
+        &mut idt.device_not_available,
+        device_not_available_handler::entry,
+    );
+    unsafe {
+        set_entry(&mut idt.double_fault, double_fault_handler::entry)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+    }
+    set_entry(&mut idt.invalid_tss, invalid_tss_handler::entry);
+    set_entry(
+        &mut idt.segment_not_present,
+        segment_not_present_handler::entry,
+    );
+    set_entry(
+        &mut idt.stack_segment_fault,
+        stack_segment_fault_handler::entry,
+    );
+    set_entry(
+        &mut idt.general_protection_fault,
+        general_protection_fault_handler::entry,
+    );
+    set_entry(&mut idt.page_fault, page_fault_entry);
+    set_entry(
+        &mut idt.x87_floating_point,
+        x87_floating_point_handler::entry,
+    );
+    set_entry(&mut idt.alignment_check, alignment_check_handler::entry);
+    set_entry(&mut idt.machine_check, machine_check_handler::entry);
+    set_entry(
+        &mut idt.simd_floating_point,
+        simd_floating_point_handler::entry,
+    );
+    set_entry(&mut idt.virtualization, virtualization_handler::entry);
+    set_entry(
+        &mut idt.cp_protection_exception,
+        control_protection_handler::entry,
+    );
+    set_entry(
+        &mut idt.hv_injection_exception,
+        hypervisor_injection_handler::entry,
+    );
+    set_entry(
+        &mut idt.vmm_communication_exception,
+        vmm_communication_handler::entry,
+    );
+    set_entry(&mut idt.security_exception, security_handler::entry);
+}
+
+extern "C" fn debug_handler(stack_frame: &InterruptStackFrame, _: u64, registers: &Registers) {
+    report(DEBUG, ErrorCode::None, stack_frame, registers);
+}
+
+extern "C" fn non_maskable_interrupt_handler(
+    stack_frame: &InterruptStackFrame,
+    _: u64,
+    registers: &Registers,
+) {
+    report(
+        NON_MASKABLE_INTERRUPT,
+        ErrorCode::None,
+        stack_frame,
+        registers,
+    );
+}
+
+/// Breakpoints are expected and resumed, so they are only reported on the
+/// screen, which keeps them out of the serial output of tests.
+extern "C" fn breakpoint_handler(stack_frame: &InterruptStackFrame, _: u64, registers: &Registers) {
+    let report = Report {
+        exception: BREAKPOINT,
+        error_code: ErrorCode::None,
+        stack_frame,
+        registers,
+    };
+    eprintln!("{}", report);
+}
+
+extern "C" fn page_fault_handler(
+    stack_frame: &InterruptStackFrame,
+    error_code: u64,
+    registers: &Registers,
+) {
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    let address = Cr2::read();
+    if memory::demand::handle_page_fault(address, error_code) {
+        return;
+    }
+
+    report(
+        PAGE_FAULT,
+        ErrorCode::Page(error_code),
+        stack_frame,
+        registers,
+    );
+    print_page_fault_details(address);
+    fatal_page_fault(address)
+}
+
+/// Prints what is known about the faulting address to VGA and serial.
+fn print_page_fault_details(address: VirtAddr) {
+    eprintln!("Accessed Address: {:?}", address);
+    serial_println!("Accessed Address: {:?}", address);
+    if memory::stack::is_guard_page(address) {
+        eprintln!("Guard page of a kernel stack hit: stack overflow");
+        serial_println!("Guard page of a kernel stack hit: stack overflow");
+    }
+    if let Some(walk) = memory::walk::walk(address) {
+        eprintln!("{}", walk);
+        serial_println!("{}", walk);
+    }
+}
+
+fn fatal_page_fault(address: VirtAddr) -> ! {
+    panic!("EXCEPTION: {} at {:?}", PAGE_FAULT.name, address)
+}
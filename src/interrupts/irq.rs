@@ -0,0 +1,262 @@
+use super::{Controller, PIC_1_OFFSET, PICS, apic, controller};
+use core::ptr;
+use spin::Mutex;
+use x86_64::{
+    instructions::{interrupts, port::Port},
+    structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame},
+};
+
+/// The number of legacy IRQ lines, which are delivered at the vectors
+/// `vector(0)..vector(IRQ_LINES)`.
+pub const IRQ_LINES: u8 = 16;
+
+pub const TIMER: u8 = 0;
+pub const KEYBOARD: u8 = 1;
+/// The line the secondary PIC is connected to, which can not be handled.
+pub const CASCADE: u8 = 2;
+/// The lines on which the primary and the secondary PIC raise spurious
+/// interrupts.
+const SPURIOUS_PRIMARY: u8 = 7;
+const SPURIOUS_SECONDARY: u8 = 15;
+
+/// The command ports of the primary and the secondary PIC.
+const PIC_COMMAND_PORTS: [u16; 2] = [0x20, 0xa0];
+/// The OCW3 command that selects the in-service register for the next read
+/// of the command port.
+const READ_ISR: u8 = 0x0b;
+
+/// The maximum number of handlers that can share a line.
+const MAX_HANDLERS_PER_LINE: usize = 4;
+
+/// A handler for an IRQ line, which is called with the line that raised the
+/// interrupt.
+///
+/// Handlers run with interrupts disabled and must not block. The end of the
+/// interrupt is signaled after all handlers of the line ran.
+pub type IrqHandler = fn(irq: u8);
+
+/// Identifies a registered handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandlerId {
+    irq: u8,
+    slot: usize,
+    /// The generation of the slot, so that a stale id does not unregister a
+    /// later handler in the same slot.
+    generation: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The line does not exist or is the cascade of the PICs.
+    InvalidLine,
+    /// All handler slots of the line are in use.
+    TooManyHandlers,
+    /// The handler was already unregistered.
+    NotFound,
+    /// The handler is already registered for the line.
+    AlreadyRegistered,
+}
+
+/// A handler slot of a line.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    handler: Option<IrqHandler>,
+    /// Counts the handlers registered in this slot.
+    generation: u32,
+}
+
+type Handlers = [Slot; MAX_HANDLERS_PER_LINE];
+
+/// The registered handlers of every line, in a fixed size table because they
+/// are called in interrupt context.
+static HANDLERS: Mutex<[Handlers; IRQ_LINES as usize]> = Mutex::new(
+    [[Slot {
+        handler: None,
+        generation: 0,
+    }; MAX_HANDLERS_PER_LINE]; IRQ_LINES as usize],
+);
+
+/// Returns the interrupt vector of an IRQ line.
+pub const fn vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
+}
+
+/// Registers a handler for an IRQ line and unmasks the line if it is the
+/// first handler.
+///
+/// A handler can only be registered once per line.
+pub fn register(irq: u8, handler: IrqHandler) -> Result<IrqHandlerId, IrqError> {
+    if irq >= IRQ_LINES || irq == CASCADE {
+        return Err(IrqError::InvalidLine);
+    }
+    interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let line = &mut handlers[usize::from(irq)];
+        if line
+            .iter()
+            .filter_map(|slot| slot.handler)
+            .any(|registered| ptr::fn_addr_eq(registered, handler))
+        {
+            return Err(IrqError::AlreadyRegistered);
+        }
+        let slot = line
+            .iter()
+            .position(|slot| slot.handler.is_none())
+            .ok_or(IrqError::TooManyHandlers)?;
+        let generation = line[slot].generation.wrapping_add(1);
+        line[slot] = Slot {
+            handler: Some(handler),
+            generation,
+        };
+        if line.iter().filter(|slot| slot.handler.is_some()).count() == 1 {
+            set_masked(irq, false);
+        }
+        Ok(IrqHandlerId {
+            irq,
+            slot,
+            generation,
+        })
+    })
+}
+
+/// Unregisters a handler registered by `register` and masks the line if it
+/// was the last handler.
+pub fn unregister(id: IrqHandlerId) -> Result<(), IrqError> {
+    interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let line = &mut handlers[usize::from(id.irq)];
+        let slot = &mut line[id.slot];
+        if slot.generation != id.generation {
+            return Err(IrqError::NotFound);
+        }
+        slot.handler.take().ok_or(IrqError::NotFound)?;
+        if line.iter().all(|slot| slot.handler.is_none()) {
+            set_masked(id.irq, true);
+        }
+        Ok(())
+    })
+}
+
+/// Returns whether any handler is registered for the line.
+pub fn is_handled(irq: u8) -> bool {
+    interrupts::without_interrupts(|| {
+        HANDLERS
+            .lock()
+            .get(usize::from(irq))
+            .is_some_and(|line| line.iter().any(|slot| slot.handler.is_some()))
+    })
+}
+
+/// Remaps the 8259 PICs behind the exception vectors and masks all lines
+/// that have no handler.
+pub(super) fn init_pics() {
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        pics.write_masks(!(1 << CASCADE), 0xff);
+    }
+    drop(pics);
+    for irq in (0..IRQ_LINES).filter(|&irq| is_handled(irq)) {
+        set_masked(irq, false);
+    }
+}
+
+/// Installs the dispatch stubs of all lines.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    for (irq, stub) in (0..IRQ_LINES).zip(STUBS) {
+        idt[usize::from(vector(irq))].set_handler_fn(stub);
+    }
+}
+
+/// Signals the end of the interrupt to the controller that delivered it.
+fn end_of_interrupt(irq: u8) {
+    match controller() {
+        Controller::Apic => apic::end_of_interrupt(),
+        Controller::Pic => unsafe { PICS.lock().notify_end_of_interrupt(vector(irq)) },
+    }
+}
+
+/// Masks or unmasks a line in the controller that delivers it.
+fn set_masked(irq: u8, masked: bool) {
+    match controller() {
+        Controller::Apic => apic::set_masked(irq, masked),
+        Controller::Pic => {
+            let mut pics = PICS.lock();
+            let mut masks = unsafe { pics.read_masks() };
+            let (pic, bit) = (usize::from(irq / 8), irq % 8);
+            if masked {
+                masks[pic] |= 1 << bit;
+            } else {
+                masks[pic] &= !(1 << bit);
+            }
+            unsafe { pics.write_masks(masks[0], masks[1]) };
+        }
+    }
+}
+
+/// Returns whether an interrupt on the line was raised by a PIC without a
+/// device requesting it.
+///
+/// The PICs raise IRQ 7 or 15 if a request disappears before the processor
+/// acknowledges it, but do not mark the line as in service then. The PICs are
+/// only masked while the APICs are used, so they can still raise these on the
+/// same vectors, which the Local APIC then has not marked as in service.
+fn is_spurious(irq: u8) -> bool {
+    if !matches!(irq, SPURIOUS_PRIMARY | SPURIOUS_SECONDARY) {
+        return false;
+    }
+    if controller() == Controller::Apic {
+        return !apic::is_in_service(vector(irq));
+    }
+    let _pics = PICS.lock();
+    let mut command = Port::<u8>::new(PIC_COMMAND_PORTS[usize::from(irq / 8)]);
+    let in_service = unsafe {
+        command.write(READ_ISR);
+        command.read()
+    };
+    in_service & (1 << (irq % 8)) == 0
+}
+
+/// Calls all handlers of the line and signals the end of the interrupt.
+fn dispatch(irq: u8) {
+    if is_spurious(irq) {
+        // the primary PIC did see the request on the cascade, so it still
+        // needs an end of interrupt
+        if irq == SPURIOUS_SECONDARY && controller() == Controller::Pic {
+            unsafe { PICS.lock().notify_end_of_interrupt(vector(CASCADE)) };
+        }
+        return;
+    }
+
+    // copy the handlers, so that handlers can (un)register handlers without
+    // holding the lock
+    let handlers = HANDLERS.lock()[usize::from(irq)];
+    for handler in handlers.iter().filter_map(|slot| slot.handler) {
+        handler(irq);
+    }
+    end_of_interrupt(irq);
+}
+
+extern "x86-interrupt" fn dispatch_stub<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(IRQ);
+}
+
+/// The entry of every line in the IDT.
+const STUBS: [HandlerFunc; IRQ_LINES as usize] = [
+    dispatch_stub::<0>,
+    dispatch_stub::<1>,
+    dispatch_stub::<2>,
+    dispatch_stub::<3>,
+    dispatch_stub::<4>,
+    dispatch_stub::<5>,
+    dispatch_stub::<6>,
+    dispatch_stub::<7>,
+    dispatch_stub::<8>,
+    dispatch_stub::<9>,
+    dispatch_stub::<10>,
+    dispatch_stub::<11>,
+    dispatch_stub::<12>,
+    dispatch_stub::<13>,
+    dispatch_stub::<14>,
+    dispatch_stub::<15>,
+];
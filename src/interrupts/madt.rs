@@ -0,0 +1,226 @@
+use core::mem;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The number of I/O APICs that are used, further ones are ignored.
+pub const MAX_IO_APICS: usize = 4;
+
+/// The number of legacy ISA IRQs.
+pub const ISA_IRQS: usize = 16;
+
+/// The address of the Local APIC if the MADT does not override it.
+const DEFAULT_LOCAL_APIC: u64 = 0xfee0_0000;
+/// The address of the I/O APIC under QEMU and most chipsets.
+const DEFAULT_IO_APIC: u64 = 0xfec0_0000;
+
+/// An I/O APIC and the first global system interrupt it handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysAddr,
+    pub gsi_base: u32,
+}
+
+/// The global system interrupt a legacy ISA IRQ is connected to, with its
+/// electrical properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsaIrq {
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+impl IsaIrq {
+    /// ISA IRQs are edge-triggered and active high unless overridden.
+    const fn identity(irq: u8) -> Self {
+        IsaIrq {
+            gsi: irq as u32,
+            active_low: false,
+            level_triggered: false,
+        }
+    }
+}
+
+/// The interrupt controllers of the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Madt {
+    pub local_apic: PhysAddr,
+    pub io_apics: [Option<IoApicInfo>; MAX_IO_APICS],
+    pub isa_irqs: [IsaIrq; ISA_IRQS],
+    /// Whether the machine also has 8259 PICs, which must be masked.
+    pub has_8259: bool,
+}
+
+impl Madt {
+    /// Reads the MADT of the ACPI tables, or returns `None` if there is no
+    /// valid one.
+    ///
+    /// The tables are read through the mapping of the physical memory at
+    /// `physical_memory_offset`.
+    pub fn read(physical_memory_offset: VirtAddr) -> Option<Madt> {
+        let memory = PhysicalMemory(physical_memory_offset);
+        let rsdp = find_rsdp(memory)?;
+        let table = find_table(memory, rsdp, *b"APIC")?;
+        Some(parse(memory, table))
+    }
+
+    /// The configuration of QEMU's q35 and i440fx machines, used when there
+    /// are no ACPI tables.
+    pub fn qemu_defaults() -> Madt {
+        let mut isa_irqs = core::array::from_fn(|irq| IsaIrq::identity(irq as u8));
+        // the PIT is connected to pin 2 of the I/O APIC
+        isa_irqs[0].gsi = 2;
+        Madt {
+            local_apic: PhysAddr::new(DEFAULT_LOCAL_APIC),
+            io_apics: [
+                Some(IoApicInfo {
+                    id: 0,
+                    address: PhysAddr::new(DEFAULT_IO_APIC),
+                    gsi_base: 0,
+                }),
+                None,
+                None,
+                None,
+            ],
+            isa_irqs,
+            has_8259: true,
+        }
+    }
+}
+
+/// Read-only access to physical memory through the bootloader's mapping.
+#[derive(Debug, Clone, Copy)]
+struct PhysicalMemory(VirtAddr);
+
+impl PhysicalMemory {
+    fn read<T: Copy>(self, addr: u64) -> T {
+        let ptr = (self.0 + addr).as_ptr::<T>();
+        unsafe { ptr.read_unaligned() }
+    }
+
+    /// Returns whether the bytes of `addr..addr + len` sum up to zero.
+    fn checksum_ok(self, addr: u64, len: u64) -> bool {
+        (addr..addr + len).fold(0u8, |sum, a| sum.wrapping_add(self.read::<u8>(a))) == 0
+    }
+}
+
+/// The signature of the RSDP, which is 16-byte aligned.
+const RSDP_SIGNATURE: [u8; 8] = *b"RSD PTR ";
+/// The length of the ACPI 1.0 part of the RSDP.
+const RSDP_V1_LENGTH: u64 = 20;
+/// The length of the header of every system description table.
+const SDT_HEADER_LENGTH: u64 = 36;
+
+/// Where the RSDP was found and which root table it points to.
+#[derive(Debug, Clone, Copy)]
+enum RootTable {
+    Rsdt(u64),
+    Xsdt(u64),
+}
+
+/// Searches the first KiB of the EBDA and the BIOS area below 1 MiB for the
+/// RSDP.
+fn find_rsdp(memory: PhysicalMemory) -> Option<RootTable> {
+    let ebda = u64::from(memory.read::<u16>(0x40e)) << 4;
+    let ebda_area = (ebda != 0).then_some(ebda..ebda + 1024);
+    let bios_area = 0xe_0000..0x10_0000;
+
+    let rsdp = ebda_area
+        .into_iter()
+        .chain([bios_area])
+        .flat_map(|area| area.step_by(16))
+        .find(|&addr| {
+            memory.read::<[u8; 8]>(addr) == RSDP_SIGNATURE
+                && memory.checksum_ok(addr, RSDP_V1_LENGTH)
+        })?;
+
+    let revision = memory.read::<u8>(rsdp + 15);
+    if revision >= 2 {
+        let length = u64::from(memory.read::<u32>(rsdp + 20));
+        let xsdt = memory.read::<u64>(rsdp + 24);
+        if xsdt != 0 && memory.checksum_ok(rsdp, length) {
+            return Some(RootTable::Xsdt(xsdt));
+        }
+    }
+    Some(RootTable::Rsdt(u64::from(memory.read::<u32>(rsdp + 16))))
+}
+
+/// Returns the address of the valid table with the given signature.
+fn find_table(memory: PhysicalMemory, root: RootTable, signature: [u8; 4]) -> Option<u64> {
+    let (root, entry_size) = match root {
+        RootTable::Rsdt(addr) => (addr, mem::size_of::<u32>() as u64),
+        RootTable::Xsdt(addr) => (addr, mem::size_of::<u64>() as u64),
+    };
+    let length = u64::from(memory.read::<u32>(root + 4));
+    if length < SDT_HEADER_LENGTH || !memory.checksum_ok(root, length) {
+        return None;
+    }
+
+    (root + SDT_HEADER_LENGTH..root + length)
+        .step_by(entry_size as usize)
+        .map(|entry| match entry_size {
+            4 => u64::from(memory.read::<u32>(entry)),
+            _ => memory.read::<u64>(entry),
+        })
+        .filter(|&table| memory.read::<[u8; 4]>(table) == signature)
+        .find(|&table| memory.checksum_ok(table, u64::from(memory.read::<u32>(table + 4))))
+}
+
+/// The types of the MADT entries that are used.
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// The MADT flag for machines with 8259 PICs.
+const PCAT_COMPAT: u32 = 1;
+
+fn parse(memory: PhysicalMemory, madt: u64) -> Madt {
+    let length = u64::from(memory.read::<u32>(madt + 4));
+    let flags = memory.read::<u32>(madt + 40);
+    let mut result = Madt {
+        local_apic: PhysAddr::new(u64::from(memory.read::<u32>(madt + 36))),
+        io_apics: [None; MAX_IO_APICS],
+        isa_irqs: core::array::from_fn(|irq| IsaIrq::identity(irq as u8)),
+        has_8259: flags & PCAT_COMPAT != 0,
+    };
+
+    let mut entry = madt + SDT_HEADER_LENGTH + 8;
+    while entry + 2 <= madt + length {
+        let entry_type = memory.read::<u8>(entry);
+        let entry_length = u64::from(memory.read::<u8>(entry + 1));
+        if entry_length < 2 {
+            break;
+        }
+
+        match entry_type {
+            ENTRY_IO_APIC => {
+                let io_apic = IoApicInfo {
+                    id: memory.read(entry + 2),
+                    address: PhysAddr::new(u64::from(memory.read::<u32>(entry + 4))),
+                    gsi_base: memory.read(entry + 8),
+                };
+                if let Some(slot) = result.io_apics.iter_mut().find(|slot| slot.is_none()) {
+                    *slot = Some(io_apic);
+                }
+            }
+            ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+                let irq = usize::from(memory.read::<u8>(entry + 3));
+                let gsi = memory.read::<u32>(entry + 4);
+                let flags = memory.read::<u16>(entry + 8);
+                if let Some(isa_irq) = result.isa_irqs.get_mut(irq) {
+                    // a polarity or trigger mode of 0 keeps the ISA default
+                    *isa_irq = IsaIrq {
+                        gsi,
+                        active_low: flags & 0b11 == 0b11,
+                        level_triggered: (flags >> 2) & 0b11 == 0b11,
+                    };
+                }
+            }
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                result.local_apic = PhysAddr::new(memory.read::<u64>(entry + 4));
+            }
+            _ => {}
+        }
+        entry += entry_length;
+    }
+    result
+}
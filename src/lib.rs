@@ -7,6 +7,7 @@
 // Change test function name to allow calling from _start
 #![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)]
+#![feature(allocator_api)]
 pub mod interrupts;
 
 pub mod gdt;
@@ -78,6 +79,56 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
     hlt_loop()
 }
 
+/// Panic handler for tests that expect a panic whose message contains
+/// `expected`, e.g. the panic of an exception handler.
+pub fn test_expected_panic_handler(info: &PanicInfo, expected: &str) -> ! {
+    let mut message = MessageBuffer::new();
+    // a message that does not fit is truncated
+    let _ = fmt::write(&mut message, format_args!("{}", info.message()));
+
+    if message.as_str().contains(expected) {
+        serial_println!("{}", Green("[ok]"));
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("{}", Red("[failed]"));
+        serial_println!("{} expected a panic with {:?}: {}\n", Red("Error:"), expected, info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop()
+}
+
+/// A fixed-size buffer for formatting without the heap.
+struct MessageBuffer {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl MessageBuffer {
+    const fn new() -> Self {
+        MessageBuffer {
+            bytes: [0; 256],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // only whole strs are written
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
 // Custom test runner
 pub fn test_runner(tests: &[&dyn Testable]) {
     serial_println!("\nRunning {} tests", tests.len());
@@ -115,9 +166,7 @@ impl fmt::Display for Red {
 pub fn init() {
     interrupts::init_idt();
     gdt::init();
-    unsafe {
-        interrupts::PICS.lock().initialize();
-    }
+    interrupts::init_irqs();
     x86_64::instructions::interrupts::enable();
 }
 
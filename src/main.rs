@@ -9,14 +9,11 @@
 #![reexport_test_harness_main = "test_main"]
 
 use bib_os::{
-    allocator, init,
-    memory::{self, BootInfoFrameAllocator},
-    println,
+    allocator, gdt, init, interrupts, memory, println,
     task::{Task, executor::Executor, keyboard},
 };
 use bootloader::{BootInfo, entry_point};
 use core::panic::PanicInfo;
-use x86_64::VirtAddr;
 extern crate alloc;
 
 entry_point!(kernel_main);
@@ -29,11 +26,14 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     println!("Hello, World{}", "!");
 
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::init_global(boot_info) };
+    gdt::init_ist_stacks().expect("interrupt stack initialization failed");
 
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    allocator::init_heap().expect("heap initialization failed");
+
+    if let Err(err) = interrupts::init_controller() {
+        println!("APIC unavailable, using the 8259 PICs: {:?}", err);
+    }
 
     let mut executor = Executor::new();
     executor.spawn(Task::new(example_task()));
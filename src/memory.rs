@@ -1,9 +1,68 @@
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+pub mod bitmap;
+pub mod buddy;
+pub mod demand;
+pub mod mmio;
+pub mod protection;
+pub mod stack;
+pub mod vmm;
+pub mod walk;
+
+use crate::allocator::Locked;
+use bootloader::BootInfo;
+use buddy::BuddyFrameAllocator;
+use conquer_once::spin::OnceCell;
 use x86_64::{
-    PhysAddr, VirtAddr,
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    VirtAddr,
+    structures::paging::{OffsetPageTable, PageTable},
 };
 
+/// The kernel's page table mapper, set up by `init_global`.
+///
+/// Lock `MAPPER` before `FRAME_ALLOCATOR` and never allocate on the heap while
+/// holding either of them, as the heap allocator needs both to grow the heap.
+/// Both disable interrupts while locked, because the heap can also grow from
+/// allocations in interrupt handlers.
+pub static MAPPER: Locked<Option<OffsetPageTable<'static>>> = Locked::new(None);
+
+/// The kernel's physical frame allocator, set up by `init_global`.
+///
+/// This is a buddy allocator, so that 2 MiB pages can be backed by contiguous
+/// frames.
+pub static FRAME_ALLOCATOR: Locked<Option<BuddyFrameAllocator>> = Locked::new(None);
+
+/// The virtual address at which the bootloader mapped the physical memory.
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Returns the offset of the physical memory mapping, or `None` if
+/// `init_global` was not called yet.
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    PHYSICAL_MEMORY_OFFSET.get().copied()
+}
+
+/// Initialize the kernel's page table mapper and frame allocator and store
+/// them in `MAPPER` and `FRAME_ALLOCATOR`.
+///
+/// This also enforces W^X on the kernel image, see `protection::enforce_wx`.
+///
+/// # Safety
+/// This function is unsafe because the caller must guarantee that the boot
+/// info comes from the bootloader, so that the complete physical memory is
+/// mapped at its `physical_memory_offset` and its memory map is valid. Also,
+/// this function must be only called once.
+pub unsafe fn init_global(boot_info: &'static BootInfo) {
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { init(physical_memory_offset) };
+    unsafe { protection::enforce_wx(&mut mapper) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, physical_memory_offset) };
+
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    PHYSICAL_MEMORY_OFFSET
+        .try_init_once(|| physical_memory_offset)
+        .expect("init_global should only be called once");
+}
+
 /// Initialize a new OffsetPageTable.
 ///
 /// # Safety
@@ -26,55 +85,19 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
-
-    let (level_4_table_frame, _) = Cr3::read();
-
-    let phys = level_4_table_frame.start_address();
-    let virt = physical_memory_offset + phys.as_u64();
-    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+    let page_table_ptr = active_level_4_table_ptr(physical_memory_offset);
 
     unsafe { &mut *page_table_ptr }
 }
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
-}
-
-impl BootInfoFrameAllocator {
-    /// Create a FrameAllocator from the passed memory map.
-    ///
-    /// # Safety
-    /// This function is unsafe because the caller must guarantee that the passed
-    /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
-        }
-    }
+/// Returns a pointer to the active level 4 table, assuming that the physical
+/// memory is mapped at `physical_memory_offset`.
+fn active_level_4_table_ptr(physical_memory_offset: VirtAddr) -> *mut PageTable {
+    use x86_64::registers::control::Cr3;
 
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
-}
+    let (level_4_table_frame, _) = Cr3::read();
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
-    }
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    virt.as_mut_ptr()
 }
@@ -0,0 +1,138 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// A FrameAllocator that keeps one bit per physical frame.
+///
+/// A set bit means that the frame is free. The bitmap itself is stored in the
+/// first usable region that is large enough to hold it.
+///
+/// The global frame allocator is the `BuddyFrameAllocator`, which also hands
+/// out contiguous runs for huge pages. This allocator only serves single
+/// frames, for memory that is not owned by the global allocator.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    /// Index of the first word that may contain a free frame.
+    next: usize,
+    total_frames: usize,
+    free_frames: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Create a BitmapFrameAllocator from the passed memory map.
+    ///
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid and that the complete physical memory is mapped to
+    /// virtual memory at the passed `physical_memory_offset`. All frames that
+    /// are marked as `USABLE` in the memory map must be really unused. This
+    /// function must be only called once.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // one bit for every frame up to the end of the last usable region
+        let frame_count = usable_regions()
+            .map(|r| r.range.end_frame_number)
+            .max()
+            .unwrap_or(0) as usize;
+        let words = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_frames = (words * 8).div_ceil(FRAME_SIZE as usize) as u64;
+
+        // store the bitmap at the start of the first region that can hold it
+        let bitmap_start = usable_regions()
+            .find(|r| r.range.end_frame_number - r.range.start_frame_number >= bitmap_frames)
+            .expect("no usable region large enough for the frame bitmap")
+            .range
+            .start_frame_number;
+
+        let virt = physical_memory_offset + bitmap_start * FRAME_SIZE;
+        let bitmap = unsafe { slice::from_raw_parts_mut(virt.as_mut_ptr::<u64>(), words) };
+        bitmap.fill(0);
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            next: 0,
+            total_frames: 0,
+            free_frames: 0,
+        };
+
+        for region in usable_regions() {
+            for frame in region.range.start_frame_number..region.range.end_frame_number {
+                let frame = frame as usize;
+                if (frame as u64) < bitmap_start || (frame as u64) >= bitmap_start + bitmap_frames {
+                    allocator.set_free(frame);
+                    allocator.total_frames += 1;
+                }
+            }
+        }
+        allocator.free_frames = allocator.total_frames;
+
+        allocator
+    }
+
+    /// Returns the number of frames managed by this allocator.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Returns the number of frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    fn is_free(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn set_free(&mut self, frame: usize) {
+        self.bitmap[frame / BITS_PER_WORD] |= 1 << (frame % BITS_PER_WORD);
+    }
+
+    fn set_used(&mut self, frame: usize) {
+        self.bitmap[frame / BITS_PER_WORD] &= !(1 << (frame % BITS_PER_WORD));
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // all words before `next` are known to be full
+        let index = self.bitmap[self.next..]
+            .iter()
+            .position(|&word| word != 0)?
+            + self.next;
+        self.next = index;
+
+        let frame = index * BITS_PER_WORD + self.bitmap[index].trailing_zeros() as usize;
+        self.set_used(frame);
+        self.free_frames -= 1;
+
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            frame as u64 * FRAME_SIZE,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let frame = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        assert!(
+            frame / BITS_PER_WORD < self.bitmap.len(),
+            "frame is not managed by this allocator"
+        );
+        assert!(!self.is_free(frame), "frame deallocated twice");
+
+        self.set_free(frame);
+        self.free_frames += 1;
+        self.next = self.next.min(frame / BITS_PER_WORD);
+    }
+}
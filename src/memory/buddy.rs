@@ -0,0 +1,267 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+    },
+};
+
+const FRAME_SIZE: u64 = 4096;
+
+/// The largest block order, a block of order `n` spans `2^n` frames.
+///
+/// Order 10 blocks are 4 MiB, which is enough to serve 2 MiB aligned regions.
+pub const MAX_ORDER: usize = 10;
+
+/// The order of a block that backs a 2 MiB page.
+const HUGE_PAGE_ORDER: usize = 9;
+
+/// Marks a frame that is not the first frame of a free block.
+const NOT_FREE: u8 = u8::MAX;
+/// Marks the end of a free list.
+const NONE: u64 = u64::MAX;
+
+/// Node of a doubly linked free list, stored inside the free block itself.
+#[derive(Clone, Copy)]
+struct FreeBlock {
+    prev: u64,
+    next: u64,
+}
+
+/// A buddy system allocator that hands out physically contiguous runs of
+/// `2^order` frames from the bootloader's memory map.
+///
+/// Blocks of order `n` always start at a frame number that is a multiple of
+/// `2^n`, so they are naturally aligned to their own size.
+pub struct BuddyFrameAllocator {
+    /// First frame number of each free list.
+    free_lists: [u64; MAX_ORDER + 1],
+    /// For every frame, the order of the free block starting at that frame
+    /// or `NOT_FREE`.
+    orders: &'static mut [u8],
+    physical_memory_offset: VirtAddr,
+    total_frames: usize,
+    free_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Create a BuddyFrameAllocator from the passed memory map.
+    ///
+    /// # Safety
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid and that the complete physical memory is mapped to
+    /// virtual memory at the passed `physical_memory_offset`. All frames that
+    /// are marked as `USABLE` in the memory map must be really unused. This
+    /// function must be only called once.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // one byte of metadata for every frame up to the end of the last usable region
+        let frame_count = usable_regions()
+            .map(|r| r.range.end_frame_number)
+            .max()
+            .unwrap_or(0);
+        let metadata_frames = frame_count.div_ceil(FRAME_SIZE);
+
+        // store the metadata at the start of the first region that can hold it
+        let metadata_start = usable_regions()
+            .find(|r| r.range.end_frame_number - r.range.start_frame_number >= metadata_frames)
+            .expect("no usable region large enough for the buddy metadata")
+            .range
+            .start_frame_number;
+
+        let virt = physical_memory_offset + metadata_start * FRAME_SIZE;
+        let orders =
+            unsafe { slice::from_raw_parts_mut(virt.as_mut_ptr::<u8>(), frame_count as usize) };
+        orders.fill(NOT_FREE);
+
+        let mut allocator = BuddyFrameAllocator {
+            free_lists: [NONE; MAX_ORDER + 1],
+            orders,
+            physical_memory_offset,
+            total_frames: 0,
+            free_frames: 0,
+        };
+
+        for region in usable_regions() {
+            let mut start = region.range.start_frame_number;
+            let end = region.range.end_frame_number;
+            if start == metadata_start {
+                start += metadata_frames;
+            }
+
+            // split the region into the largest aligned blocks that fit
+            while start < end {
+                let mut order = (start.trailing_zeros() as usize).min(MAX_ORDER);
+                while start + (1 << order) > end {
+                    order -= 1;
+                }
+                allocator.free_block(start, order);
+                allocator.total_frames += 1 << order;
+                start += 1 << order;
+            }
+        }
+
+        allocator
+    }
+
+    /// Returns the number of frames managed by this allocator.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Returns the number of frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Returns the smallest order whose blocks can hold `size` bytes.
+    pub fn order_for(size: u64) -> usize {
+        let frames = size.div_ceil(FRAME_SIZE).max(1);
+        frames.next_power_of_two().trailing_zeros() as usize
+    }
+
+    /// Allocates a run of `2^order` contiguous frames whose start address is
+    /// aligned to `align` bytes.
+    ///
+    /// Requires that `align` is a power of two.
+    pub fn allocate(&mut self, order: usize, align: u64) -> Option<PhysFrame> {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        // blocks are aligned to their own size, so allocate a block that is
+        // large enough for the alignment and give back the excess
+        let align_order = Self::order_for(align);
+        let block_order = order.max(align_order);
+        if block_order > MAX_ORDER {
+            return None;
+        }
+
+        let mut current = (block_order..=MAX_ORDER).find(|&o| self.free_lists[o] != NONE)?;
+        let frame = self.free_lists[current];
+        self.remove_block(frame, current);
+        self.free_frames -= 1 << current;
+
+        // split the block until it has the requested order
+        while current > order {
+            current -= 1;
+            self.free_block(frame + (1 << current), current);
+        }
+
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            frame * FRAME_SIZE,
+        )))
+    }
+
+    /// Frees a run of `2^order` frames previously returned by `allocate`.
+    ///
+    /// # Safety
+    /// The caller must ensure that the run was allocated with the same `order`
+    /// and that it is unused.
+    pub unsafe fn deallocate(&mut self, frame: PhysFrame, order: usize) {
+        let frame = frame.start_address().as_u64() / FRAME_SIZE;
+        assert!(order <= MAX_ORDER, "invalid block order");
+        assert_eq!(frame % (1 << order), 0, "block is not aligned to its order");
+        assert!(
+            frame + (1 << order) <= self.orders.len() as u64,
+            "block is not managed by this allocator"
+        );
+        assert!(
+            !self.overlaps_free_block(frame, order),
+            "block deallocated twice"
+        );
+
+        self.free_block(frame, order);
+    }
+
+    /// Returns whether any frame of the block is part of a free block, either
+    /// of a larger free block containing it or of a free block inside it.
+    fn overlaps_free_block(&self, frame: u64, order: usize) -> bool {
+        let containing = (order..=MAX_ORDER).any(|o| {
+            let start = frame & !((1 << o) - 1);
+            self.orders[start as usize] == o as u8
+        });
+        let inside = self.orders[frame as usize..(frame + (1 << order)) as usize]
+            .iter()
+            .any(|&o| o != NOT_FREE);
+        containing || inside
+    }
+
+    /// Inserts the given block into the free lists, merging it with its buddy
+    /// as long as the buddy is free as well.
+    fn free_block(&mut self, mut frame: u64, mut order: usize) {
+        self.free_frames += 1 << order;
+
+        while order < MAX_ORDER {
+            let buddy = frame ^ (1 << order);
+            if buddy >= self.orders.len() as u64 || self.orders[buddy as usize] != order as u8 {
+                break;
+            }
+            self.remove_block(buddy, order);
+            frame = frame.min(buddy);
+            order += 1;
+        }
+
+        self.push_block(frame, order);
+    }
+
+    /// Returns the free list node stored in the given frame.
+    fn node(&mut self, frame: u64) -> &mut FreeBlock {
+        let virt = self.physical_memory_offset + frame * FRAME_SIZE;
+        unsafe { &mut *virt.as_mut_ptr::<FreeBlock>() }
+    }
+
+    /// Adds the given block to the front of the free list of its order.
+    fn push_block(&mut self, frame: u64, order: usize) {
+        let next = self.free_lists[order];
+        *self.node(frame) = FreeBlock { prev: NONE, next };
+        if next != NONE {
+            self.node(next).prev = frame;
+        }
+        self.free_lists[order] = frame;
+        self.orders[frame as usize] = order as u8;
+    }
+
+    /// Unlinks the given block from the free list of its order.
+    fn remove_block(&mut self, frame: u64, order: usize) {
+        let FreeBlock { prev, next } = *self.node(frame);
+        if prev == NONE {
+            self.free_lists[order] = next;
+        } else {
+            self.node(prev).next = next;
+        }
+        if next != NONE {
+            self.node(next).prev = prev;
+        }
+        self.orders[frame as usize] = NOT_FREE;
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate(0, FRAME_SIZE)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        unsafe { self.deallocate(frame, 0) }
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.allocate(HUGE_PAGE_ORDER, Size2MiB::SIZE)?;
+        Some(PhysFrame::from_start_address(frame.start_address()).unwrap())
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let frame = PhysFrame::containing_address(frame.start_address());
+        unsafe { self.deallocate(frame, HUGE_PAGE_ORDER) }
+    }
+}
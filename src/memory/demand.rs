@@ -0,0 +1,155 @@
+use super::{
+    FRAME_ALLOCATOR, MAPPER,
+    vmm::{self, VirtualRegion, VmmError},
+};
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
+            mapper::MapToError,
+        },
+    },
+};
+
+/// The maximum number of demand paged regions that can be registered.
+const MAX_REGIONS: usize = 32;
+
+/// The registered regions, each a range of virtual pages reserved in the VMM
+/// that is backed by frames on first access.
+///
+/// This is a fixed size table so that the page fault handler never needs to
+/// allocate on the heap.
+static REGIONS: Mutex<[Option<VirtualRegion>; MAX_REGIONS]> = Mutex::new([None; MAX_REGIONS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemandRegionError {
+    /// The range overlaps an already registered region or another
+    /// reservation of the VMM.
+    Overlap,
+    /// The range is not inside the kernel address space or no free range
+    /// of the requested size is left.
+    OutOfAddressSpace,
+    /// All region slots or all reservation slots of the VMM are in use.
+    TooManyRegions,
+    /// No region starts at the given address.
+    NotFound,
+}
+
+impl From<VmmError> for DemandRegionError {
+    fn from(err: VmmError) -> Self {
+        match err {
+            VmmError::Overlap => DemandRegionError::Overlap,
+            VmmError::OutOfAddressSpace => DemandRegionError::OutOfAddressSpace,
+            VmmError::TooManyReservations => DemandRegionError::TooManyRegions,
+            err => unreachable!("reserving address space failed with {:?}", err),
+        }
+    }
+}
+
+/// Reserves `pages` free pages of kernel address space as demand paged and
+/// returns the first page.
+///
+/// Nothing is mapped until the pages are accessed; the page fault handler then
+/// maps each touched page to a zeroed frame with the given `flags`.
+pub fn reserve(pages: u64, flags: PageTableFlags) -> Result<Page, DemandRegionError> {
+    register(|| vmm::reserve(pages, flags))
+}
+
+/// Reserves the `pages` starting at `start` as demand paged, like `reserve`.
+pub fn reserve_at(start: Page, pages: u64, flags: PageTableFlags) -> Result<(), DemandRegionError> {
+    register(|| vmm::reserve_at(start, pages, flags)).map(|_| ())
+}
+
+/// Registers the range reserved by `reserve_range` in a free slot.
+fn register(
+    reserve_range: impl FnOnce() -> Result<VirtualRegion, VmmError>,
+) -> Result<Page, DemandRegionError> {
+    let mut regions = REGIONS.lock();
+    let slot = regions
+        .iter_mut()
+        .find(|r| r.is_none())
+        .ok_or(DemandRegionError::TooManyRegions)?;
+    let region = reserve_range()?;
+    *slot = Some(region);
+    Ok(region.pages().start)
+}
+
+/// Unregisters the region starting at `start` and frees its address space,
+/// unmapping all of its pages that were touched and returning their frames to
+/// the frame allocator.
+///
+/// # Safety
+/// The caller must ensure that the memory of the region is no longer in use.
+pub unsafe fn release(start: Page) -> Result<(), DemandRegionError> {
+    let region = {
+        let mut regions = REGIONS.lock();
+        regions
+            .iter_mut()
+            .find(|r| r.is_some_and(|r| r.pages().start == start))
+            .and_then(Option::take)
+            .ok_or(DemandRegionError::NotFound)?
+    };
+
+    unsafe { vmm::release(region).expect("demand paged region not reserved") };
+    Ok(())
+}
+
+/// Tries to resolve a page fault at `addr` by backing the faulting page with
+/// a zeroed frame.
+///
+/// Returns `true` if the page was mapped and the faulting instruction can be
+/// retried, or `false` if the fault is not caused by a demand paged region.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    // never spin in the fault handler: the interrupted code may hold the lock
+    let Some(regions) = REGIONS.try_lock() else {
+        return false;
+    };
+    let Some(region) = regions
+        .iter()
+        .flatten()
+        .find(|r| r.pages().start <= page && page < r.pages().end)
+        .copied()
+    else {
+        return false;
+    };
+    drop(regions);
+
+    let (Some(mut mapper), Some(mut frame_allocator)) =
+        (MAPPER.try_lock(), FRAME_ALLOCATOR.try_lock())
+    else {
+        return false;
+    };
+    let (Some(mapper), Some(frame_allocator)) = (mapper.as_mut(), frame_allocator.as_mut()) else {
+        return false;
+    };
+
+    let Some(frame) = frame_allocator.allocate_frame() else {
+        return false;
+    };
+    let frame_ptr: *mut u8 = (mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { frame_ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+
+    match unsafe { mapper.map_to(page, frame, region.flags(), frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        // another access mapped the page in the meantime
+        Err(MapToError::PageAlreadyMapped(_)) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            true
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            false
+        }
+    }
+}
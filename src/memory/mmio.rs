@@ -0,0 +1,117 @@
+use super::vmm::{self, VirtualRegion, VmmError};
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB},
+};
+
+/// Caching flags for device registers: every access goes straight to the device.
+pub const UNCACHED: PageTableFlags = PageTableFlags::NO_CACHE.union(PageTableFlags::WRITE_THROUGH);
+
+/// A mapping of device memory into kernel address space.
+///
+/// All accesses are volatile and bounds checked. The mapping is removed when
+/// the handle is dropped.
+#[derive(Debug)]
+pub struct MmioRegion {
+    region: VirtualRegion,
+    base: VirtAddr,
+    size: u64,
+}
+
+impl MmioRegion {
+    /// Maps `size` bytes of device memory starting at `phys` with the given
+    /// caching flags, e.g. `UNCACHED`.
+    ///
+    /// The mapping is always writable and never executable.
+    ///
+    /// # Safety
+    /// The caller must ensure that the physical range belongs to a device and
+    /// not to RAM managed by the frame allocator, and that no other mapping
+    /// uses incompatible caching flags for it.
+    pub unsafe fn map(phys: PhysAddr, size: u64, flags: PageTableFlags) -> Result<Self, VmmError> {
+        let start_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+        let offset = phys - start_frame.start_address();
+        let pages = (offset + size).div_ceil(Size4KiB::SIZE);
+
+        let flags = flags | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let region = vmm::reserve(pages, flags)?;
+        if let Err(err) = unsafe { vmm::map_physical(&region, start_frame) } {
+            unsafe { vmm::release_physical(region)? };
+            return Err(err);
+        }
+
+        Ok(MmioRegion {
+            region,
+            base: region.start() + offset,
+            size,
+        })
+    }
+
+    /// Returns the virtual address of the first mapped byte.
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// Returns the size of the mapped device memory in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn read_u8(&self, offset: u64) -> u8 {
+        unsafe { self.ptr::<u8>(offset).read_volatile() }
+    }
+
+    pub fn read_u16(&self, offset: u64) -> u16 {
+        unsafe { self.ptr::<u16>(offset).read_volatile() }
+    }
+
+    pub fn read_u32(&self, offset: u64) -> u32 {
+        unsafe { self.ptr::<u32>(offset).read_volatile() }
+    }
+
+    pub fn read_u64(&self, offset: u64) -> u64 {
+        unsafe { self.ptr::<u64>(offset).read_volatile() }
+    }
+
+    pub fn write_u8(&mut self, offset: u64, value: u8) {
+        unsafe { self.ptr::<u8>(offset).write_volatile(value) }
+    }
+
+    pub fn write_u16(&mut self, offset: u64, value: u16) {
+        unsafe { self.ptr::<u16>(offset).write_volatile(value) }
+    }
+
+    pub fn write_u32(&mut self, offset: u64, value: u32) {
+        unsafe { self.ptr::<u32>(offset).write_volatile(value) }
+    }
+
+    pub fn write_u64(&mut self, offset: u64, value: u64) {
+        unsafe { self.ptr::<u64>(offset).write_volatile(value) }
+    }
+
+    /// Returns a pointer to a `T` at `offset`, panicking if it is out of
+    /// bounds or misaligned.
+    fn ptr<T>(&self, offset: u64) -> *mut T {
+        let size = core::mem::size_of::<T>() as u64;
+        assert!(
+            offset.checked_add(size).is_some_and(|end| end <= self.size),
+            "MMIO access at offset {:#x} out of bounds",
+            offset
+        );
+        let addr = self.base + offset;
+        assert!(
+            addr.is_aligned(core::mem::align_of::<T>() as u64),
+            "misaligned MMIO access at offset {:#x}",
+            offset
+        );
+        addr.as_mut_ptr()
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        unsafe {
+            vmm::release_physical(self.region).expect("MMIO region not reserved");
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use x86_64::{
+    VirtAddr,
+    instructions::tlb,
+    registers::control::{Cr0, Cr0Flags, Efer, EferFlags},
+    structures::paging::{Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, Size4KiB},
+};
+
+/// ELF program header type of loadable segments.
+const PT_LOAD: u32 = 1;
+/// ELF segment permission flags.
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+unsafe extern "C" {
+    /// The ELF header of the kernel, defined by the linker.
+    static __ehdr_start: u8;
+}
+
+/// An ELF64 program header.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+impl ProgramHeader {
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = Page::containing_address(VirtAddr::new(self.p_vaddr));
+        let end = Page::containing_address(VirtAddr::new(self.p_vaddr + self.p_memsz - 1));
+        Page::range_inclusive(start, end)
+    }
+
+    fn contains(&self, page: Page) -> bool {
+        let start = page.start_address().as_u64();
+        self.p_vaddr < start + 4096 && start < self.p_vaddr + self.p_memsz
+    }
+}
+
+/// Returns the program headers of the loadable kernel segments.
+fn load_segments() -> impl Iterator<Item = ProgramHeader> + Clone {
+    let ehdr = &raw const __ehdr_start;
+    let (phoff, phentsize, phnum) = unsafe {
+        (
+            ehdr.add(0x20).cast::<u64>().read_unaligned(),
+            ehdr.add(0x36).cast::<u16>().read_unaligned(),
+            ehdr.add(0x38).cast::<u16>().read_unaligned(),
+        )
+    };
+
+    (0..phnum as usize)
+        .map(move |i| unsafe {
+            ehdr.add(phoff as usize + i * phentsize as usize)
+                .cast::<ProgramHeader>()
+                .read_unaligned()
+        })
+        .filter(|ph| ph.p_type == PT_LOAD && ph.p_memsz > 0)
+}
+
+/// Enforces that no kernel memory is both writable and executable.
+///
+/// Enables `EFER.NXE` and `CR0.WP`, then remaps the kernel's code as
+/// read-only and executable, its read-only data as read-only and
+/// non-executable and its data and bss as writable and non-executable. All
+/// other writable pages, like the bootloader's mapping of the physical
+/// memory, the kernel stack and the boot info, are made non-executable too.
+///
+/// # Safety
+/// The caller must guarantee that `mapper` maps the running kernel.
+pub unsafe fn enforce_wx(mapper: &mut OffsetPageTable) {
+    unsafe {
+        Efer::update(|efer| efer.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|cr0| cr0.insert(Cr0Flags::WRITE_PROTECT));
+    }
+
+    let segments = load_segments();
+    for segment in segments.clone() {
+        for page in segment.pages() {
+            // a page shared by two segments needs the permissions of both
+            let (writable, executable) = segments
+                .clone()
+                .filter(|s| s.contains(page))
+                .fold((false, false), |(w, x), s| {
+                    (w || s.p_flags & PF_W != 0, x || s.p_flags & PF_X != 0)
+                });
+            assert!(
+                !(writable && executable),
+                "kernel page {:?} is both writable and executable",
+                page
+            );
+
+            let mut flags = PageTableFlags::PRESENT;
+            if writable {
+                flags |= PageTableFlags::WRITABLE;
+            }
+            if !executable {
+                flags |= PageTableFlags::NO_EXECUTE;
+            }
+            unsafe { Mapper::<Size4KiB>::update_flags(mapper, page, flags) }
+                .expect("kernel page not mapped")
+                .flush();
+        }
+    }
+
+    let offset = mapper.phys_offset();
+    unsafe { forbid_execute_if_writable(mapper.level_4_table(), 4, offset) };
+    tlb::flush_all();
+}
+
+/// Sets `NO_EXECUTE` on every writable page mapped by the table, which is on
+/// the given level of the page table hierarchy.
+///
+/// # Safety
+/// The physical memory must be mapped at `offset`, and no page that is being
+/// executed may be writable.
+unsafe fn forbid_execute_if_writable(table: &mut PageTable, level: u8, offset: VirtAddr) {
+    for entry in table.iter_mut() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            if flags.contains(PageTableFlags::WRITABLE) {
+                entry.set_flags(flags | PageTableFlags::NO_EXECUTE);
+            }
+        } else {
+            let next = unsafe { &mut *(offset + entry.addr().as_u64()).as_mut_ptr() };
+            unsafe { forbid_execute_if_writable(next, level - 1, offset) };
+        }
+    }
+}
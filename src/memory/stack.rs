@@ -0,0 +1,89 @@
+use super::vmm::{self, VirtualRegion, VmmError};
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageSize, PageTableFlags, Size4KiB},
+};
+
+/// The maximum number of stacks that can be allocated at the same time.
+const MAX_STACKS: usize = 64;
+
+/// The guard pages of all allocated stacks, used to recognize stack overflows.
+static GUARD_PAGES: Mutex<[Option<Page>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/// A kernel stack with an unmapped guard page below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelStack {
+    region: VirtualRegion,
+}
+
+impl KernelStack {
+    /// Returns the initial stack pointer, the end of the stack.
+    pub fn top(&self) -> VirtAddr {
+        self.region.start() + self.region.size()
+    }
+
+    /// Returns the lowest usable address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.guard_page().start_address() + Size4KiB::SIZE
+    }
+
+    /// Returns the unmapped page directly below the stack.
+    pub fn guard_page(&self) -> Page {
+        self.region.pages().start
+    }
+}
+
+/// Allocates a stack of `pages` mapped pages with an unmapped guard page
+/// below it, so that overflowing the stack causes a page fault.
+pub fn alloc_stack(pages: u64) -> Result<KernelStack, VmmError> {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = vmm::reserve(pages + 1, flags)?;
+    let guard_page = region.pages().start;
+
+    let registered = match GUARD_PAGES.lock().iter_mut().find(|p| p.is_none()) {
+        Some(slot) => {
+            *slot = Some(guard_page);
+            Ok(())
+        }
+        None => Err(VmmError::TooManyReservations),
+    };
+
+    // the lowest page of the region stays unmapped as guard page
+    let stack_pages = Page::range(guard_page + 1, region.pages().end);
+    if let Err(err) = registered.and_then(|()| vmm::map_pages(&region, stack_pages)) {
+        unsafe { free_region(region) };
+        return Err(err);
+    }
+    Ok(KernelStack { region })
+}
+
+/// Unmaps the stack and returns its frames to the frame allocator.
+///
+/// # Safety
+/// The caller must ensure that the stack is no longer in use.
+pub unsafe fn free_stack(stack: KernelStack) {
+    unsafe { free_region(stack.region) };
+}
+
+unsafe fn free_region(region: VirtualRegion) {
+    let guard_page = region.pages().start;
+    if let Some(slot) = GUARD_PAGES
+        .lock()
+        .iter_mut()
+        .find(|p| **p == Some(guard_page))
+    {
+        *slot = None;
+    }
+    unsafe { vmm::release(region).expect("stack not reserved") };
+}
+
+/// Returns whether `addr` lies in the guard page of an allocated stack.
+///
+/// Does not block, so that it can be used by the page fault handler.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    let page = Page::containing_address(addr);
+    GUARD_PAGES
+        .try_lock()
+        .is_some_and(|guard_pages| guard_pages.contains(&Some(page)))
+}
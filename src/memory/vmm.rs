@@ -0,0 +1,353 @@
+use super::{FRAME_ALLOCATOR, MAPPER, buddy::BuddyFrameAllocator};
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags,
+        PhysFrame, Size2MiB, Size4KiB,
+        mapper::{CleanUp, MapToError, UnmapError},
+        page::PageRange,
+    },
+};
+
+/// Start of the kernel's virtual address space in the higher half.
+///
+/// The bootloader only uses the lower half, so the whole higher half (except
+/// for the last level 4 entry) is managed here.
+pub const KERNEL_SPACE_START: u64 = 0xffff_8000_0000_0000;
+/// End (exclusive) of the kernel's virtual address space.
+pub const KERNEL_SPACE_END: u64 = 0xffff_ff80_0000_0000;
+
+/// The maximum number of ranges that can be reserved at the same time.
+const MAX_RESERVATIONS: usize = 64;
+/// The number of 4 KiB pages covered by a 2 MiB page.
+const HUGE_PAGE_PAGES: u64 = Size2MiB::SIZE / Size4KiB::SIZE;
+
+/// A reserved range of kernel virtual pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualRegion {
+    pages: PageRange,
+    flags: PageTableFlags,
+}
+
+impl VirtualRegion {
+    /// Returns the first address of the region.
+    pub fn start(&self) -> VirtAddr {
+        self.pages.start.start_address()
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> u64 {
+        self.pages.end.start_address() - self.pages.start.start_address()
+    }
+
+    /// Returns the pages of the region.
+    pub fn pages(&self) -> PageRange {
+        self.pages
+    }
+
+    /// Returns the flags the pages of the region are mapped with.
+    pub fn flags(&self) -> PageTableFlags {
+        self.flags
+    }
+
+    fn overlaps(&self, pages: PageRange) -> bool {
+        self.pages.start < pages.end && pages.start < self.pages.end
+    }
+}
+
+#[derive(Debug)]
+pub enum VmmError {
+    /// The range overlaps an existing reservation.
+    Overlap,
+    /// The range is not inside the kernel address space or no free range
+    /// of the requested size is left.
+    OutOfAddressSpace,
+    /// All reservation slots are in use.
+    TooManyReservations,
+    /// The pages are not part of the given reservation.
+    NotReserved,
+    /// Mapping a page failed.
+    MapFailed(MapToError<Size4KiB>),
+}
+
+/// The reserved ranges.
+///
+/// This is a fixed size table so that reserving address space never needs to
+/// allocate on the heap, which may itself need to reserve address space.
+static RESERVATIONS: Mutex<[Option<VirtualRegion>; MAX_RESERVATIONS]> =
+    Mutex::new([None; MAX_RESERVATIONS]);
+
+/// Reserves `pages` free pages of kernel address space, which are mapped with
+/// the given `flags` by `map`.
+///
+/// Regions of at least 2 MiB are 2 MiB aligned, so that `map` can back them
+/// with huge pages.
+pub fn reserve(pages: u64, flags: PageTableFlags) -> Result<VirtualRegion, VmmError> {
+    let mut reservations = RESERVATIONS.lock();
+    let align = if pages >= HUGE_PAGE_PAGES {
+        Size2MiB::SIZE
+    } else {
+        Size4KiB::SIZE
+    };
+
+    // first fit: move past every reservation the candidate range collides with
+    let mut start = Page::containing_address(VirtAddr::new(KERNEL_SPACE_START));
+    let end = Page::containing_address(VirtAddr::new(KERNEL_SPACE_END));
+    loop {
+        start = Page::containing_address(start.start_address().align_up(align));
+        if end - start < pages {
+            return Err(VmmError::OutOfAddressSpace);
+        }
+        let candidate = Page::range(start, start + pages);
+        let collision = reservations
+            .iter()
+            .flatten()
+            .find(|r| r.overlaps(candidate))
+            .map(|r| r.pages.end);
+        match collision {
+            Some(end) => start = end,
+            None => return insert(&mut reservations[..], candidate, flags),
+        }
+    }
+}
+
+/// Reserves `pages` pages of kernel address space starting at `start`.
+pub fn reserve_at(
+    start: Page,
+    pages: u64,
+    flags: PageTableFlags,
+) -> Result<VirtualRegion, VmmError> {
+    let candidate = Page::range(start, start + pages);
+    if candidate.start.start_address().as_u64() < KERNEL_SPACE_START
+        || candidate.end.start_address().as_u64() > KERNEL_SPACE_END
+    {
+        return Err(VmmError::OutOfAddressSpace);
+    }
+
+    let mut reservations = RESERVATIONS.lock();
+    if reservations.iter().flatten().any(|r| r.overlaps(candidate)) {
+        return Err(VmmError::Overlap);
+    }
+    insert(&mut reservations[..], candidate, flags)
+}
+
+fn insert(
+    reservations: &mut [Option<VirtualRegion>],
+    pages: PageRange,
+    flags: PageTableFlags,
+) -> Result<VirtualRegion, VmmError> {
+    let region = VirtualRegion {
+        pages,
+        flags: flags | PageTableFlags::PRESENT,
+    };
+    let slot = reservations
+        .iter_mut()
+        .find(|r| r.is_none())
+        .ok_or(VmmError::TooManyReservations)?;
+    *slot = Some(region);
+    Ok(region)
+}
+
+/// Maps all pages of the region to newly allocated frames.
+pub fn map(region: &VirtualRegion) -> Result<(), VmmError> {
+    map_pages(region, region.pages)
+}
+
+/// Maps the given pages of the region to newly allocated frames.
+///
+/// Every 2 MiB aligned run of 2 MiB within the pages is mapped with a huge
+/// page if a contiguous 2 MiB block of frames is available, the remaining
+/// pages are mapped with 4 KiB pages.
+///
+/// If a page can not be mapped, the pages mapped so far are unmapped again
+/// and their frames are returned to the frame allocator.
+pub fn map_pages(region: &VirtualRegion, pages: PageRange) -> Result<(), VmmError> {
+    if pages.start < region.pages.start || pages.end > region.pages.end {
+        return Err(VmmError::NotReserved);
+    }
+
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().expect("memory not initialized");
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory not initialized");
+
+    let mut page = pages.start;
+    while page < pages.end {
+        if let Some(huge_page) = huge_page_at(page, pages.end) {
+            let frame = FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator);
+            if let Some(frame) = frame {
+                let result =
+                    unsafe { mapper.map_to(huge_page, frame, region.flags, frame_allocator) };
+                match result {
+                    Ok(flush) => {
+                        flush.flush();
+                        page += HUGE_PAGE_PAGES;
+                        continue;
+                    }
+                    // e.g. a page table already exists, use 4 KiB pages instead
+                    Err(_) => unsafe { frame_allocator.deallocate_frame(frame) },
+                }
+            }
+        }
+
+        let result = FrameAllocator::<Size4KiB>::allocate_frame(frame_allocator)
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| {
+                unsafe { mapper.map_to(page, frame, region.flags, frame_allocator) }
+                    .map(|flush| flush.flush())
+                    .inspect_err(|_| unsafe { frame_allocator.deallocate_frame(frame) })
+            });
+
+        if let Err(err) = result {
+            let mapped = Page::range(pages.start, page);
+            unsafe { unmap_range(mapper, frame_allocator, mapped, true) };
+            return Err(VmmError::MapFailed(err));
+        }
+        page += 1;
+    }
+
+    Ok(())
+}
+
+/// Maps the pages of the region to the physical frames starting at `start`.
+///
+/// Unlike `map`, no frames are allocated, which makes this suitable for
+/// memory that is not managed by the frame allocator, such as device memory.
+/// Huge pages are used where both the pages and the frames are 2 MiB aligned.
+///
+/// # Safety
+/// The caller must ensure that the frames are not in use by anything else,
+/// since the mapping gives unrestricted access to them.
+pub unsafe fn map_physical(region: &VirtualRegion, start: PhysFrame) -> Result<(), VmmError> {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().expect("memory not initialized");
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory not initialized");
+
+    let mut page = region.pages.start;
+    while page < region.pages.end {
+        let frame = start + (page - region.pages.start);
+        let huge_frame = PhysFrame::<Size2MiB>::from_start_address(frame.start_address());
+        if let Some(huge_page) = huge_page_at(page, region.pages.end)
+            && let Ok(huge_frame) = huge_frame
+            && let Ok(flush) =
+                unsafe { mapper.map_to(huge_page, huge_frame, region.flags, frame_allocator) }
+        {
+            flush.flush();
+            page += HUGE_PAGE_PAGES;
+            continue;
+        }
+
+        let result = unsafe { mapper.map_to(page, frame, region.flags, frame_allocator) };
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                let mapped = Page::range(region.pages.start, page);
+                unsafe { unmap_range(mapper, frame_allocator, mapped, false) };
+                return Err(VmmError::MapFailed(err));
+            }
+        }
+        page += 1;
+    }
+
+    Ok(())
+}
+
+/// Unmaps all mapped pages of the region and returns their frames to the
+/// frame allocator. The address space stays reserved.
+///
+/// # Safety
+/// The caller must ensure that the memory of the region is no longer in use.
+pub unsafe fn unmap(region: &VirtualRegion) {
+    unsafe { unmap_pages(region, true) }
+}
+
+/// Unmaps the region like `unmap` and frees its address space.
+///
+/// # Safety
+/// The caller must ensure that the memory of the region is no longer in use.
+pub unsafe fn release(region: VirtualRegion) -> Result<(), VmmError> {
+    unsafe { release_region(region, true) }
+}
+
+/// Unmaps a region mapped by `map_physical` and frees its address space.
+///
+/// The frames are not returned to the frame allocator.
+///
+/// # Safety
+/// The caller must ensure that the memory of the region is no longer in use.
+pub unsafe fn release_physical(region: VirtualRegion) -> Result<(), VmmError> {
+    unsafe { release_region(region, false) }
+}
+
+unsafe fn release_region(region: VirtualRegion, free_frames: bool) -> Result<(), VmmError> {
+    let mut reservations = RESERVATIONS.lock();
+    let slot = reservations
+        .iter_mut()
+        .find(|r| **r == Some(region))
+        .ok_or(VmmError::NotReserved)?;
+
+    unsafe { unmap_pages(&region, free_frames) };
+    *slot = None;
+    Ok(())
+}
+
+unsafe fn unmap_pages(region: &VirtualRegion, free_frames: bool) {
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().expect("memory not initialized");
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory not initialized");
+
+    unsafe { unmap_range(mapper, frame_allocator, region.pages, free_frames) };
+}
+
+/// Returns the 2 MiB page starting at `page` if it is aligned and ends
+/// before `end`.
+fn huge_page_at(page: Page, end: Page) -> Option<Page<Size2MiB>> {
+    if end - page < HUGE_PAGE_PAGES {
+        return None;
+    }
+    Page::from_start_address(page.start_address()).ok()
+}
+
+/// Unmaps all mapped 4 KiB and 2 MiB pages in `pages` and frees the page
+/// tables that became empty.
+///
+/// Huge pages must lie completely inside `pages`.
+unsafe fn unmap_range(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+    pages: PageRange,
+    free_frames: bool,
+) {
+    if pages.is_empty() {
+        return;
+    }
+
+    let mut page = pages.start;
+    while page < pages.end {
+        match mapper.unmap(page) {
+            Ok((frame, flush)) => {
+                flush.flush();
+                if free_frames {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+                page += 1;
+            }
+            Err(UnmapError::ParentEntryHugePage) => {
+                let huge_page = Page::<Size2MiB>::containing_address(page.start_address());
+                let (frame, flush) = mapper.unmap(huge_page).expect("huge page not mapped");
+                flush.flush();
+                if free_frames {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+                page = Page::containing_address(huge_page.start_address()) + HUGE_PAGE_PAGES;
+            }
+            Err(_) => page += 1,
+        }
+    }
+
+    let range = Page::range_inclusive(pages.start, pages.end - 1);
+    unsafe { mapper.clean_up_addr_range(range, frame_allocator) };
+}
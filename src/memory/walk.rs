@@ -0,0 +1,214 @@
+use super::{active_level_4_table_ptr, physical_memory_offset};
+use crate::serial_println;
+use core::fmt;
+use x86_64::{PhysAddr, VirtAddr, structures::paging::PageTableFlags};
+
+/// Mask of the physical address bits of a page table entry.
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+/// Number of entries in a page table.
+const ENTRY_COUNT: u64 = 512;
+
+/// A single page table entry visited during a walk.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkEntry {
+    /// The level of the table containing the entry, 4 for the level 4 table.
+    pub level: u8,
+    /// The index of the entry in its table.
+    pub index: u16,
+    pub flags: PageTableFlags,
+    /// The frame the entry points to, either the next table or the mapped page.
+    pub addr: PhysAddr,
+}
+
+impl WalkEntry {
+    fn new(level: u8, index: u16, raw: u64) -> Self {
+        WalkEntry {
+            level,
+            index,
+            flags: PageTableFlags::from_bits_truncate(raw),
+            addr: PhysAddr::new(raw & ADDRESS_MASK),
+        }
+    }
+
+    /// Returns whether the entry maps a page instead of pointing to a table.
+    pub fn is_leaf(&self) -> bool {
+        self.level == 1 || (self.level < 4 && self.flags.contains(PageTableFlags::HUGE_PAGE))
+    }
+
+    /// Returns the size of the memory covered by the entry.
+    pub fn page_size(&self) -> u64 {
+        4096 << (9 * (self.level as u64 - 1))
+    }
+}
+
+/// The entries visited while translating a virtual address, starting at the
+/// level 4 table.
+#[derive(Debug, Clone, Copy)]
+pub struct PageWalk {
+    pub addr: VirtAddr,
+    entries: [Option<WalkEntry>; 4],
+}
+
+impl PageWalk {
+    /// Returns the visited entries, from the level 4 table downwards.
+    pub fn entries(&self) -> impl Iterator<Item = &WalkEntry> {
+        self.entries.iter().flatten()
+    }
+
+    /// Returns the entry that maps the page containing the address, if the
+    /// address is mapped.
+    pub fn leaf(&self) -> Option<&WalkEntry> {
+        self.entries()
+            .last()
+            .filter(|e| e.flags.contains(PageTableFlags::PRESENT) && e.is_leaf())
+    }
+
+    /// Returns the physical address the virtual address is mapped to.
+    pub fn translate(&self) -> Option<PhysAddr> {
+        let leaf = self.leaf()?;
+        let size = leaf.page_size();
+        let frame = leaf.addr.align_down(size);
+        Some(frame + (self.addr.as_u64() & (size - 1)))
+    }
+}
+
+impl fmt::Display for PageWalk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Page table walk for {:?}:", self.addr)?;
+        for entry in self.entries() {
+            write!(
+                f,
+                "\n  P{}[{:3}] {:#014x} {:?}",
+                entry.level,
+                entry.index,
+                entry.addr.as_u64(),
+                entry.flags
+            )?;
+            if entry.level > 1 && entry.is_leaf() {
+                write!(f, " ({} KiB page)", entry.page_size() / 1024)?;
+            }
+        }
+        match self.translate() {
+            Some(phys) => write!(f, "\n  -> {:?}", phys),
+            None => write!(f, "\n  -> not mapped"),
+        }
+    }
+}
+
+/// Walks the active page table for the given address.
+///
+/// Returns `None` if the physical memory offset is not known yet.
+pub fn walk(addr: VirtAddr) -> Option<PageWalk> {
+    let offset = physical_memory_offset()?;
+    let mut walk = PageWalk {
+        addr,
+        entries: [None; 4],
+    };
+
+    let mut table = active_level_4_table_ptr(offset) as *const u64;
+    for (i, level) in (1..=4u8).rev().enumerate() {
+        let index = (addr.as_u64() >> (12 + 9 * (level as u64 - 1))) % ENTRY_COUNT;
+        let raw = unsafe { table.add(index as usize).read_volatile() };
+        let entry = WalkEntry::new(level, index as u16, raw);
+        walk.entries[i] = Some(entry);
+
+        if !entry.flags.contains(PageTableFlags::PRESENT) || entry.is_leaf() {
+            break;
+        }
+        table = (offset + entry.addr.as_u64()).as_ptr();
+    }
+
+    Some(walk)
+}
+
+/// A contiguous range of virtual memory that is mapped to contiguous
+/// physical memory with the same flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub start: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: u64,
+    pub flags: PageTableFlags,
+}
+
+impl MappedRange {
+    /// Extends the range by `next` if it directly follows it.
+    fn merge(&mut self, next: &MappedRange) -> bool {
+        // compare raw addresses, since the end of the lower half is not canonical
+        let follows = self.start.as_u64() + self.size == next.start.as_u64()
+            && self.phys.as_u64() + self.size == next.phys.as_u64()
+            && self.flags == next.flags;
+        if follows {
+            self.size += next.size;
+        }
+        follows
+    }
+}
+
+/// Calls `f` for every mapped range of the active page table, in ascending
+/// virtual address order. Adjacent pages are coalesced into a single range.
+///
+/// Does nothing if the physical memory offset is not known yet.
+pub fn for_each_mapping(mut f: impl FnMut(MappedRange)) {
+    let Some(offset) = physical_memory_offset() else {
+        return;
+    };
+
+    let mut current: Option<MappedRange> = None;
+    let level_4_table = active_level_4_table_ptr(offset) as *const u64;
+    visit_table(offset, level_4_table, 4, 0, &mut |range| {
+        let merged = current.as_mut().is_some_and(|c| c.merge(&range));
+        if !merged && let Some(done) = current.replace(range) {
+            f(done);
+        }
+    });
+    if let Some(done) = current {
+        f(done);
+    }
+}
+
+fn visit_table(
+    offset: VirtAddr,
+    table: *const u64,
+    level: u8,
+    base: u64,
+    f: &mut impl FnMut(MappedRange),
+) {
+    for index in 0..ENTRY_COUNT {
+        let raw = unsafe { table.add(index as usize).read_volatile() };
+        let entry = WalkEntry::new(level, index as u16, raw);
+        if !entry.flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let start = base + (index << (12 + 9 * (level as u64 - 1)));
+        if entry.is_leaf() {
+            // the accessed and dirty bits change all the time, so ignore them
+            let ignored =
+                PageTableFlags::ACCESSED | PageTableFlags::DIRTY | PageTableFlags::HUGE_PAGE;
+            f(MappedRange {
+                start: VirtAddr::new_truncate(start),
+                phys: entry.addr.align_down(entry.page_size()),
+                size: entry.page_size(),
+                flags: entry.flags - ignored,
+            });
+        } else {
+            let next = (offset + entry.addr.as_u64()).as_ptr();
+            visit_table(offset, next, level - 1, start, f);
+        }
+    }
+}
+
+/// Prints all mapped ranges of the active page table to serial.
+pub fn dump_mappings() {
+    serial_println!("Mapped ranges:");
+    for_each_mapping(|range| {
+        serial_println!(
+            "  {:#018x}-{:#018x} -> {:#014x} {:?}",
+            range.start.as_u64(),
+            range.start.as_u64() + range.size,
+            range.phys.as_u64(),
+            range.flags
+        );
+    });
+}
@@ -1,12 +1,39 @@
 use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Poll, Waker};
+use crate::allocator::slab::{SlabBox, SlabCache, SlabStats};
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering, fence},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
 use crossbeam_queue::ArrayQueue;
 
+/// The spawned tasks of all executors.
+static TASKS: SlabCache<SpawnedTask> = SlabCache::new("task");
+
+/// The wakers of the spawned tasks.
+static WAKERS: SlabCache<TaskWaker> = SlabCache::new("task waker");
+
+/// Returns the usage of the cache that holds the spawned tasks.
+pub fn task_cache_stats() -> SlabStats {
+    TASKS.stats()
+}
+
+/// Returns the usage of the cache that holds the wakers of the tasks.
+pub fn waker_cache_stats() -> SlabStats {
+    WAKERS.stats()
+}
+
+/// A spawned task and its waker, which is created when the task is first
+/// polled and reused for every further poll.
+struct SpawnedTask {
+    task: Task,
+    waker: Option<Waker>,
+}
+
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
+    tasks: BTreeMap<TaskId, SlabBox<'static, SpawnedTask>>,
     task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
 }
 
 impl Executor {
@@ -15,7 +42,6 @@ impl Executor {
         Executor {
             tasks: BTreeMap::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
         }
     }
 }
@@ -23,7 +49,8 @@ impl Executor {
 impl Executor {
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
+        let spawned = TASKS.alloc(SpawnedTask { task, waker: None });
+        if self.tasks.insert(task_id, spawned).is_some() {
             panic!("task with same ID already in tasks");
         }
         self.task_queue.push(task_id).expect("queue full");
@@ -31,26 +58,19 @@ impl Executor {
 
     fn run_ready_tasks(&mut self) {
         // destructure `self` to avoid borrow checker errors
-        let Self {
-            tasks,
-            task_queue,
-            waker_cache,
-        } = self;
+        let Self { tasks, task_queue } = self;
 
         while let Some(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
+            let SpawnedTask { task, waker } = match tasks.get_mut(&task_id) {
+                Some(spawned) => &mut **spawned,
                 None => continue, // task no longer exists
             };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let waker = waker.get_or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
             let mut context = Context::from_waker(waker);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
                     // task done -> remove it and its cached waker
                     tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
                 }
                 Poll::Pending => {}
             }
@@ -76,18 +96,25 @@ impl Executor {
     }
 }
 
+/// The target of a task's wakers, which is reference counted like an `Arc`
+/// but allocated from `WAKERS`.
 struct TaskWaker {
     task_id: TaskId,
     task_queue: Arc<ArrayQueue<TaskId>>,
+    /// The number of `Waker`s that point to this waker.
+    references: AtomicUsize,
 }
 
 impl TaskWaker {
     #[allow(clippy::new_ret_no_self)]
     fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
+        let waker = WAKERS.alloc(TaskWaker {
             task_id,
             task_queue,
-        }))
+            references: AtomicUsize::new(1),
+        });
+        let data = waker.into_raw().as_ptr() as *const ();
+        unsafe { Waker::from_raw(RawWaker::new(data, &WAKER_VTABLE)) }
     }
 
     fn wake_task(&self) {
@@ -95,12 +122,34 @@ impl TaskWaker {
     }
 }
 
-impl Wake for TaskWaker {
-    fn wake(self: Arc<Self>) {
-        self.wake_task();
+const WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    let waker = unsafe { &*(data as *const TaskWaker) };
+    waker.references.fetch_add(1, Ordering::Relaxed);
+    RawWaker::new(data, &WAKER_VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    unsafe {
+        wake_by_ref(data);
+        drop_waker(data);
     }
+}
+
+unsafe fn wake_by_ref(data: *const ()) {
+    let waker = unsafe { &*(data as *const TaskWaker) };
+    waker.wake_task();
+}
 
-    fn wake_by_ref(self: &Arc<Self>) {
-        self.wake_task();
+unsafe fn drop_waker(data: *const ()) {
+    let waker = unsafe { &*(data as *const TaskWaker) };
+    if waker.references.fetch_sub(1, Ordering::Release) != 1 {
+        return;
     }
+    // see `Arc::drop`: all uses of the waker happen before it is freed
+    fence(Ordering::Acquire);
+    let ptr = unsafe { NonNull::new_unchecked(data as *mut TaskWaker) };
+    drop(unsafe { SlabBox::from_raw(ptr, &WAKERS) });
 }
@@ -0,0 +1,121 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{
+    memory::{self, vmm},
+    serial_print,
+};
+use bootloader::{BootInfo, entry_point};
+use core::{arch::asm, panic::PanicInfo, ptr};
+use lazy_static::lazy_static;
+use x86_64::{
+    VirtAddr,
+    instructions::{
+        interrupts,
+        segmentation::{CS, Segment},
+        tables::load_tss,
+    },
+    registers::{
+        control::{Cr0, Cr0Flags},
+        rflags::RFlags,
+    },
+    structures::{
+        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        paging::PageTableFlags,
+        tss::TaskStateSegment,
+    },
+};
+
+/// Loads from a misaligned address on the stack, then executes `ud2` if no
+/// exception was raised.
+const USER_CODE: [u8; 6] = [0x8b, 0x44, 0x24, 0x01, 0x0f, 0x0b];
+
+/// The size of the stacks the processor switches to when entering the kernel.
+const STACK_SIZE: usize = 4096 * 5;
+
+struct Selectors {
+    code: SegmentSelector,
+    user_code: SegmentSelector,
+    user_data: SegmentSelector,
+    tss: SegmentSelector,
+}
+
+lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        static mut KERNEL_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let mut tss = TaskStateSegment::new();
+        tss.privilege_stack_table[0] = VirtAddr::from_ptr(&raw const KERNEL_STACK) + STACK_SIZE;
+        tss.interrupt_stack_table[bib_os::gdt::DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::from_ptr(&raw const DOUBLE_FAULT_STACK) + STACK_SIZE;
+        tss
+    };
+
+    /// A GDT with segments for user mode.
+    static ref TEST_GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        (
+            gdt,
+            Selectors {
+                code,
+                user_code,
+                user_data,
+                tss,
+            },
+        )
+    };
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("alignment_check::alignment_check...\t");
+
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    interrupts::disable();
+    TEST_GDT.0.load();
+    let selectors = &TEST_GDT.1;
+    unsafe {
+        CS::set_reg(selectors.code);
+        load_tss(selectors.tss);
+    }
+
+    // one page for the code and the stack of user mode
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let region = vmm::reserve(1, flags).expect("no address space for user mode");
+    vmm::map(&region).expect("mapping the user page failed");
+    let code = region.start();
+    let stack_top = region.start() + region.size() - 16u64;
+    unsafe { ptr::copy_nonoverlapping(USER_CODE.as_ptr(), code.as_mut_ptr(), USER_CODE.len()) };
+
+    // alignment is only checked in user mode, with both flags set
+    unsafe {
+        Cr0::update(|flags| flags.insert(Cr0Flags::ALIGNMENT_MASK));
+        asm!(
+            "push {ss}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            "iretq",
+            ss = in(reg) u64::from(selectors.user_data.0),
+            rsp = in(reg) stack_top.as_u64(),
+            rflags = in(reg) RFlags::ALIGNMENT_CHECK.bits(),
+            cs = in(reg) u64::from(selectors.user_code.0),
+            rip = in(reg) code.as_u64(),
+            options(noreturn)
+        );
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: ALIGNMENT CHECK")
+}
@@ -0,0 +1,82 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bib_os::interrupts::{self, Controller, apic, irq, madt::Madt};
+use bib_os::memory;
+use bootloader::{BootInfo, entry_point};
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    apic::init().expect("APIC initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_lists_controllers() {
+    let offset = memory::physical_memory_offset().unwrap();
+    let madt = Madt::read(offset).expect("no MADT under QEMU");
+    assert_eq!(madt.local_apic, Madt::qemu_defaults().local_apic);
+    assert!(madt.io_apics[0].is_some());
+    assert!(madt.has_8259);
+}
+
+#[test_case]
+fn apic_delivers_interrupts() {
+    assert!(apic::is_supported());
+    assert_eq!(interrupts::controller(), Controller::Apic);
+}
+
+#[test_case]
+fn local_apic_timer_ticks() {
+    let start = interrupts::ticks();
+    while interrupts::ticks() < start + 10 {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn keyboard_is_routed_and_unmasked() {
+    let redirection = apic::redirection(irq::KEYBOARD).expect("keyboard IRQ not routed");
+    assert_eq!(redirection.vector, irq::vector(irq::KEYBOARD));
+    assert!(!redirection.masked);
+}
+
+static SPURIOUS_LINE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn spurious_line_handler(_irq: u8) {
+    SPURIOUS_LINE_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn pic_spurious_vectors_are_ignored() {
+    // raised in software, so the Local APIC does not mark the vectors as in
+    // service like for a spurious interrupt of the masked PICs
+    let ids = [7, 15].map(|line| irq::register(line, spurious_line_handler).unwrap());
+    unsafe {
+        asm!("int {vector}", vector = const irq::vector(7));
+        asm!("int {vector}", vector = const irq::vector(15));
+    }
+    assert_eq!(SPURIOUS_LINE_CALLS.load(Ordering::Relaxed), 0);
+    for id in ids {
+        irq::unregister(id).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(allocator_api)]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bib_os::allocator::{self, arena::Arena};
+use bib_os::memory;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn arena_allocations_bypass_global_heap() {
+    let arena = Arena::new(16 * 1024).unwrap();
+    let before = allocator::heap_stats();
+
+    let mut vec = Vec::new_in(&arena);
+    for i in 0..1000u32 {
+        vec.push(i);
+    }
+    let boxed = Box::new_in([1u8; 100], &arena);
+    assert_eq!(vec.iter().sum::<u32>(), 999 * 1000 / 2);
+    assert_eq!(boxed[99], 1);
+
+    let after = allocator::heap_stats();
+    assert_eq!(after.allocations, before.allocations);
+    assert_eq!(after.used_bytes, before.used_bytes);
+    assert_eq!(arena.stats().allocations, 2);
+}
+
+#[test_case]
+fn last_allocation_grows_in_place() {
+    let arena = Arena::new(4096).unwrap();
+    let mut vec = Vec::<u8, _>::with_capacity_in(16, &arena);
+    vec.extend_from_slice(&[7; 16]);
+    let ptr = vec.as_ptr();
+
+    vec.reserve_exact(1024);
+    assert_eq!(vec.as_ptr(), ptr);
+    assert_eq!(vec[15], 7);
+}
+
+#[test_case]
+fn reset_frees_all_allocations() {
+    let mut arena = Arena::new(4096).unwrap();
+    let boxes: [_; 10] = core::array::from_fn(|i| Box::new_in(i, &arena));
+    // the boxes are forgotten instead of being freed one by one
+    core::mem::forget(boxes);
+    assert!(arena.stats().free_bytes < 4096);
+
+    arena.reset();
+    let stats = arena.stats();
+    assert_eq!(stats.free_bytes, 4096);
+    assert_eq!(stats.allocations, 0);
+    assert_eq!(stats.used_bytes, 0);
+}
+
+#[test_case]
+fn exhausted_arena_fails_allocations() {
+    let arena = Arena::new(1024).unwrap();
+    let mut vec = Vec::<u8, _>::new_in(&arena);
+    assert!(vec.try_reserve_exact(1024).is_ok());
+    assert!(vec.try_reserve_exact(2048).is_err());
+}
+
+#[test_case]
+fn page_backed_arena() {
+    let arena = Arena::with_pages(4).unwrap();
+    let stats = arena.stats();
+    assert_eq!(stats.total_size, 4 * 4096);
+
+    let mut large = Box::new_in([0u64; 1024], &arena);
+    large[1023] = 42;
+    assert_eq!(large[1023], 42);
+    drop(large);
+    assert_eq!(arena.stats().free_bytes, 4 * 4096);
+}
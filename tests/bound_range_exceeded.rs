@@ -0,0 +1,25 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{QemuExitCode, Red, exit_qemu, hlt_loop, serial_print, serial_println};
+use core::{arch::asm, panic::PanicInfo};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("bound_range_exceeded::bound_range_exceeded...\t");
+
+    bib_os::init();
+
+    // `bound` is invalid in 64-bit mode, so the exception is raised in software
+    unsafe { asm!("int 5") };
+
+    serial_println!("{}", Red("[no exception]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: BOUND RANGE EXCEEDED")
+}
@@ -0,0 +1,84 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bib_os::memory::{
+    self, FRAME_ALLOCATOR,
+    demand::{self, DemandRegionError},
+    vmm,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::structures::paging::PageTableFlags;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+const REGION_PAGES: u64 = 1024;
+
+fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames()
+}
+
+#[test_case]
+fn pages_are_backed_on_first_access() {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let start = demand::reserve(REGION_PAGES, flags).expect("reserve failed");
+
+    let before = free_frames();
+    let ptr: *mut u64 = start.start_address().as_mut_ptr();
+    let last = unsafe { ptr.add((REGION_PAGES * 4096) as usize / 8 - 1) };
+    unsafe {
+        assert_eq!(last.read_volatile(), 0);
+        last.write_volatile(42);
+        assert_eq!(last.read_volatile(), 42);
+    }
+    // only the touched page (and possibly its page tables) got a frame
+    assert!(before - free_frames() <= 4);
+
+    unsafe { demand::release(start).expect("release failed") };
+}
+
+#[test_case]
+fn overlapping_regions_are_rejected() {
+    let start = demand::reserve(16, PageTableFlags::WRITABLE).expect("reserve failed");
+    assert_eq!(
+        demand::reserve_at(start + 8, 16, PageTableFlags::WRITABLE),
+        Err(DemandRegionError::Overlap)
+    );
+    unsafe { demand::release(start).expect("release failed") };
+}
+
+#[test_case]
+fn regions_overlapping_vmm_reservations_are_rejected() {
+    let region = vmm::reserve(16, PageTableFlags::WRITABLE).expect("reserve failed");
+    let start = region.pages().start;
+    assert_eq!(
+        demand::reserve_at(start + 8, 16, PageTableFlags::WRITABLE),
+        Err(DemandRegionError::Overlap)
+    );
+    unsafe { vmm::release(region).expect("release failed") };
+
+    // the range is free again once the VMM released it
+    demand::reserve_at(start, 16, PageTableFlags::WRITABLE).expect("reserve failed");
+    assert!(matches!(
+        vmm::reserve_at(start, 16, PageTableFlags::WRITABLE),
+        Err(vmm::VmmError::Overlap)
+    ));
+    unsafe { demand::release(start).expect("release failed") };
+}
@@ -0,0 +1,29 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{QemuExitCode, Red, exit_qemu, hlt_loop, serial_print, serial_println};
+use core::{arch::asm, panic::PanicInfo};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("device_not_available::device_not_available...\t");
+
+    bib_os::init();
+
+    // x87 instructions fault while the task-switched flag is set
+    unsafe {
+        Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED));
+        asm!("fninit");
+    }
+
+    serial_println!("{}", Red("[no exception]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: DEVICE NOT AVAILABLE")
+}
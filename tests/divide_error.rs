@@ -0,0 +1,27 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{QemuExitCode, Red, exit_qemu, hlt_loop, serial_print, serial_println};
+use core::{arch::asm, panic::PanicInfo};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("divide_error::divide_error...\t");
+
+    bib_os::init();
+
+    // divide by zero
+    unsafe {
+        asm!("div ecx", in("ecx") 0u32, inout("eax") 1u32 => _, inout("edx") 0u32 => _);
+    }
+
+    serial_println!("{}", Red("[no exception]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: DIVIDE ERROR")
+}
@@ -0,0 +1,38 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{Layout, alloc, dealloc};
+use bib_os::{
+    QemuExitCode, Red, allocator, exit_qemu, hlt_loop, memory, serial_print, serial_println,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("double_free::double_free_is_detected...\t");
+
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout);
+    }
+
+    serial_println!("{}", Red("[double free not detected]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "heap-debug: double free")
+}
@@ -0,0 +1,172 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bib_os::allocator;
+use bib_os::memory::{
+    self, FRAME_ALLOCATOR,
+    bitmap::BitmapFrameAllocator,
+    buddy::{BuddyFrameAllocator, MAX_ORDER},
+};
+use bootloader::{
+    BootInfo,
+    bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType},
+    entry_point,
+};
+use core::panic::PanicInfo;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+const FRAME_SIZE: u64 = 4096;
+const BLOCK_FRAMES: u64 = 1 << MAX_ORDER;
+
+/// Creates a memory map of two blocks of the maximum order, taken from the
+/// global frame allocator, and returns it with the higher block's first frame
+/// number.
+///
+/// The lower region is cut to the `metadata_frames` an allocator needs for
+/// the frames up to the end of the higher block, so when the metadata goes
+/// into the lower region the whole higher block stays free.
+fn test_memory_map(metadata_frames: impl Fn(u64) -> u64) -> (&'static MemoryMap, u64) {
+    let (low, high) = {
+        let mut global = FRAME_ALLOCATOR.lock();
+        let global = global.as_mut().unwrap();
+        let a = global.allocate(MAX_ORDER, FRAME_SIZE).unwrap();
+        let b = global.allocate(MAX_ORDER, FRAME_SIZE).unwrap();
+        let (a, b) = (a.start_address().as_u64(), b.start_address().as_u64());
+        (a.min(b), a.max(b))
+    };
+
+    let metadata_frames = metadata_frames(high / FRAME_SIZE + BLOCK_FRAMES);
+    let mut memory_map = MemoryMap::new();
+    for (start, end) in [
+        (low, low + metadata_frames * FRAME_SIZE),
+        (high, high + BLOCK_FRAMES * FRAME_SIZE),
+    ] {
+        memory_map.add_region(MemoryRegion {
+            range: FrameRange::new(start, end),
+            region_type: MemoryRegionType::Usable,
+        });
+    }
+    (Box::leak(Box::new(memory_map)), high / FRAME_SIZE)
+}
+
+/// Creates a buddy allocator that manages exactly one block of the maximum
+/// order and returns the block's first frame number.
+fn test_allocator() -> (BuddyFrameAllocator, u64) {
+    // one byte per frame
+    let (memory_map, first) = test_memory_map(|frames| frames.div_ceil(FRAME_SIZE));
+    let offset = memory::physical_memory_offset().unwrap();
+    let allocator = unsafe { BuddyFrameAllocator::init(memory_map, offset) };
+    assert_eq!(allocator.total_frames(), BLOCK_FRAMES as usize);
+    (allocator, first)
+}
+
+/// Creates a bitmap allocator that manages exactly one block of the maximum
+/// order and returns the block's first frame number.
+fn test_bitmap_allocator() -> (BitmapFrameAllocator, u64) {
+    // one bit per frame, in whole words
+    let (memory_map, first) =
+        test_memory_map(|frames| (frames.div_ceil(64) * 8).div_ceil(FRAME_SIZE));
+    let offset = memory::physical_memory_offset().unwrap();
+    let allocator = unsafe { BitmapFrameAllocator::init(memory_map, offset) };
+    assert_eq!(allocator.total_frames(), BLOCK_FRAMES as usize);
+    (allocator, first)
+}
+
+fn frame_number(frame: x86_64::structures::paging::PhysFrame) -> u64 {
+    frame.start_address().as_u64() / FRAME_SIZE
+}
+
+#[test_case]
+fn runs_are_contiguous_and_aligned() {
+    let (mut allocator, first) = test_allocator();
+
+    let single = frame_number(allocator.allocate(0, FRAME_SIZE).unwrap());
+    // 4 frames aligned to 64 KiB
+    let run = frame_number(allocator.allocate(2, 16 * FRAME_SIZE).unwrap());
+    assert_eq!(run % 16, 0);
+    assert!(!(run..run + 4).contains(&single));
+    assert!((first..first + BLOCK_FRAMES).contains(&run));
+    // the excess of the aligned block was given back
+    assert_eq!(allocator.free_frames(), BLOCK_FRAMES as usize - 1 - 4);
+}
+
+#[test_case]
+fn freed_frames_merge_to_max_order() {
+    let (mut allocator, first) = test_allocator();
+
+    let frames: Vec<_> = (0..BLOCK_FRAMES)
+        .map(|_| allocator.allocate(0, FRAME_SIZE).unwrap())
+        .collect();
+    for frame in frames {
+        unsafe { allocator.deallocate(frame, 0) };
+    }
+
+    let block = allocator.allocate(MAX_ORDER, FRAME_SIZE).unwrap();
+    assert_eq!(frame_number(block), first);
+}
+
+#[test_case]
+fn exhausted_allocator_returns_none() {
+    let (mut allocator, _) = test_allocator();
+
+    let block = allocator.allocate(MAX_ORDER, FRAME_SIZE).unwrap();
+    assert_eq!(allocator.free_frames(), 0);
+    assert!(allocator.allocate(0, FRAME_SIZE).is_none());
+
+    unsafe { allocator.deallocate(block, MAX_ORDER) };
+    assert!(allocator.allocate(MAX_ORDER + 1, FRAME_SIZE).is_none());
+    assert!(allocator.allocate(MAX_ORDER, FRAME_SIZE).is_some());
+}
+
+#[test_case]
+fn bitmap_allocates_every_frame_once() {
+    let (mut allocator, first) = test_bitmap_allocator();
+
+    let frames: Vec<_> = (0..BLOCK_FRAMES)
+        .map(|_| frame_number(allocator.allocate_frame().unwrap()))
+        .collect();
+    // the frames are handed out in order
+    assert!(frames.windows(2).all(|w| w[0] + 1 == w[1]));
+    assert_eq!(frames[0], first);
+    assert_eq!(allocator.free_frames(), 0);
+    assert!(allocator.allocate_frame().is_none());
+}
+
+#[test_case]
+fn bitmap_reuses_freed_frames() {
+    let (mut allocator, _) = test_bitmap_allocator();
+
+    let a = allocator.allocate_frame().unwrap();
+    let b = allocator.allocate_frame().unwrap();
+    unsafe { allocator.deallocate_frame(a) };
+    assert_eq!(allocator.free_frames(), BLOCK_FRAMES as usize - 1);
+    // the lowest free frame is handed out first
+    assert_eq!(allocator.allocate_frame(), Some(a));
+    assert_eq!(
+        frame_number(allocator.allocate_frame().unwrap()),
+        frame_number(b) + 1
+    );
+}
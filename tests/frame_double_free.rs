@@ -0,0 +1,73 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bib_os::memory::{
+    self, FRAME_ALLOCATOR,
+    buddy::{BuddyFrameAllocator, MAX_ORDER},
+};
+use bib_os::{QemuExitCode, Red, allocator, exit_qemu, hlt_loop, serial_print, serial_println};
+use bootloader::{
+    BootInfo,
+    bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType},
+    entry_point,
+};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+const FRAME_SIZE: u64 = 4096;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("frame_double_free::free_inside_merged_block_is_detected...\t");
+
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    // manage a block of frames from the global allocator, with the metadata
+    // in the lower of two blocks
+    let (low, high) = {
+        let mut global = FRAME_ALLOCATOR.lock();
+        let global = global.as_mut().unwrap();
+        let a = global.allocate(MAX_ORDER, FRAME_SIZE).unwrap();
+        let b = global.allocate(MAX_ORDER, FRAME_SIZE).unwrap();
+        let (a, b) = (a.start_address().as_u64(), b.start_address().as_u64());
+        (a.min(b), a.max(b))
+    };
+    let metadata_frames = (high / FRAME_SIZE + (1 << MAX_ORDER)).div_ceil(FRAME_SIZE);
+    let mut memory_map = MemoryMap::new();
+    for (start, end) in [
+        (low, low + metadata_frames * FRAME_SIZE),
+        (high, high + (FRAME_SIZE << MAX_ORDER)),
+    ] {
+        memory_map.add_region(MemoryRegion {
+            range: FrameRange::new(start, end),
+            region_type: MemoryRegionType::Usable,
+        });
+    }
+    let memory_map = Box::leak(Box::new(memory_map));
+    let offset = memory::physical_memory_offset().unwrap();
+    let mut allocator = unsafe { BuddyFrameAllocator::init(memory_map, offset) };
+
+    let first = allocator.allocate(0, FRAME_SIZE).unwrap();
+    let second = allocator.allocate(0, FRAME_SIZE).unwrap();
+    unsafe {
+        allocator.deallocate(first, 0);
+        // merges the whole block again, so `second` is no block head
+        allocator.deallocate(second, 0);
+        allocator.deallocate(second, 0);
+    }
+
+    serial_println!("{}", Red("[double free not detected]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "block deallocated twice")
+}
@@ -0,0 +1,84 @@
+#![feature(abi_x86_interrupt)]
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{
+    Green, QemuExitCode, Red, exit_qemu, gdt, hlt_loop, memory, serial_print, serial_println,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use x86_64::{
+    registers::control::Cr2,
+    structures::{
+        idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+        paging::Page,
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("guard_page::write_below_ist_stack...\t");
+
+    gdt::init();
+    init_test_idt();
+    unsafe { memory::init_global(boot_info) };
+    gdt::init_ist_stacks().expect("interrupt stack initialization failed");
+
+    let stack = gdt::ist_stack(gdt::DOUBLE_FAULT_IST_INDEX).expect("no guard-paged stack");
+    assert_eq!(stack.top() - stack.bottom(), gdt::IST_STACK_PAGES * 4096);
+
+    // the whole stack is usable
+    unsafe {
+        (stack.top() - 8u64).as_mut_ptr::<u64>().write_volatile(1);
+        stack.bottom().as_mut_ptr::<u64>().write_volatile(1);
+    }
+
+    // the page below it must fault
+    unsafe {
+        (stack.bottom() - 8u64)
+            .as_mut_ptr::<u64>()
+            .write_volatile(1)
+    };
+
+    serial_println!("{}", Red("[write did not fault]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode,
+) {
+    let address = Cr2::read();
+    let guard_page = gdt::ist_stack(gdt::DOUBLE_FAULT_IST_INDEX).map(|s| s.guard_page());
+    if guard_page == Some(Page::containing_address(address))
+        && memory::stack::is_guard_page(address)
+    {
+        serial_println!("{}", Green("[ok]"));
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("{}", Red("[unexpected page fault]"));
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop()
+}
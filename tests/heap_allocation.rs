@@ -10,20 +10,17 @@ extern crate alloc;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use bib_os::allocator;
-use bib_os::allocator::HEAP_SIZE;
-use bib_os::memory::{self, BootInfoFrameAllocator};
+use bib_os::allocator::{HEAP_MAX_SIZE, HEAP_SIZE};
+use bib_os::memory;
 use bootloader::{BootInfo, entry_point};
 use core::panic::PanicInfo;
-use x86_64::VirtAddr;
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     bib_os::init();
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
 
     test_main();
     loop {}
@@ -61,3 +58,81 @@ fn many_boxes() {
     }
     assert_eq!(*long_lived, 1);
 }
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    let n = 4 * HEAP_SIZE;
+    let mut vec = Vec::with_capacity(n);
+    for i in 0..n {
+        vec.push(i as u8);
+    }
+    assert_eq!(vec.len(), n);
+    assert!(vec.iter().enumerate().all(|(i, &x)| x == i as u8));
+}
+
+#[test_case]
+fn stats_track_allocations() {
+    let before = allocator::heap_stats();
+    let small = Box::new([0u8; 24]);
+    let large = Box::new([0u8; 4096]);
+
+    let stats = allocator::heap_stats();
+    assert_eq!(stats.allocations, before.allocations + 2);
+    assert_eq!(stats.used_bytes, before.used_bytes + 24 + 4096);
+    assert!(stats.peak_used_bytes >= stats.used_bytes);
+    assert!(stats.total_size >= HEAP_SIZE);
+    assert!(stats.free_bytes < stats.total_size);
+
+    drop((small, large));
+    let after = allocator::heap_stats();
+    assert_eq!(after.allocations, before.allocations);
+    assert_eq!(after.used_bytes, before.used_bytes);
+    assert!(after.free_bytes > stats.free_bytes);
+}
+
+#[test_case]
+fn failed_allocation_returns_error() {
+    let mut vec = Vec::<u8>::new();
+    assert!(vec.try_reserve_exact(2 * HEAP_MAX_SIZE).is_err());
+}
+
+/// A cache that is dropped under memory pressure.
+#[cfg(not(feature = "alloc-bump"))]
+static CACHE: spin::Mutex<Option<Vec<u8>>> = spin::Mutex::new(None);
+
+/// The size of the cache, a bit less than half of the heap to leave room for
+/// the redzones of `heap-debug`.
+#[cfg(not(feature = "alloc-bump"))]
+const CACHE_SIZE: usize = HEAP_MAX_SIZE / 2 - 4096;
+/// The size of an allocation that only fits after the cache was dropped.
+#[cfg(not(any(feature = "alloc-bump", feature = "alloc-buddy")))]
+const PRESSURE_SIZE: usize = HEAP_MAX_SIZE * 3 / 4;
+/// The buddy allocator rounds larger allocations up to the whole heap, which
+/// is never free, so it can only fit the half the cache used.
+#[cfg(feature = "alloc-buddy")]
+const PRESSURE_SIZE: usize = CACHE_SIZE;
+
+#[cfg(not(feature = "alloc-bump"))]
+fn drop_cache(_layout: core::alloc::Layout) -> bool {
+    CACHE.lock().take().is_some()
+}
+
+// the bump allocator can not reuse freed memory
+#[cfg(not(feature = "alloc-bump"))]
+#[test_case]
+fn pressure_callbacks_free_memory() {
+    use bib_os::allocator::oom;
+
+    *CACHE.lock() = Some(Vec::with_capacity(CACHE_SIZE));
+    let id = oom::register_pressure_callback(drop_cache).unwrap();
+
+    let vec = Vec::<u8>::with_capacity(PRESSURE_SIZE);
+    assert!(CACHE.lock().is_none());
+    drop(vec);
+
+    oom::unregister_pressure_callback(id).unwrap();
+    assert_eq!(
+        oom::unregister_pressure_callback(id),
+        Err(oom::PressureCallbackError::NotFound)
+    );
+}
@@ -0,0 +1,71 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{
+    alloc::{Layout, alloc, dealloc},
+    boxed::Box,
+    vec::Vec,
+};
+use bib_os::{allocator, memory};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn fresh_memory_is_poisoned() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        assert!((0..layout.size()).all(|i| ptr.add(i).read() == 0xaa));
+        dealloc(ptr, layout);
+    }
+}
+
+/// Freeing must not be mistaken for a corruption, whatever metadata the
+/// allocator writes into the freed blocks.
+#[test_case]
+fn freeing_is_not_reported() {
+    for align in [8, 64, 4096] {
+        let layouts: Vec<Layout> = [1, 8, 24, 100, 1000, 5000]
+            .iter()
+            .map(|&size| Layout::from_size_align(size, align).unwrap())
+            .collect();
+        let ptrs: Vec<*mut u8> = layouts
+            .iter()
+            .map(|&layout| unsafe { alloc(layout) })
+            .collect();
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+        for (&ptr, &layout) in ptrs.iter().zip(&layouts) {
+            unsafe { dealloc(ptr, layout) };
+        }
+    }
+}
+
+#[test_case]
+fn reused_memory_is_not_reported() {
+    for i in 0..1000 {
+        let boxed = Box::new([i; 4]);
+        assert_eq!(boxed[3], i);
+    }
+}
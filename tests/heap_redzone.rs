@@ -0,0 +1,39 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{Layout, alloc, dealloc};
+use bib_os::{
+    QemuExitCode, Red, allocator, exit_qemu, hlt_loop, memory, serial_print, serial_println,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_redzone::overflow_is_detected...\t");
+
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        // write one byte past the end of the allocation
+        ptr.add(layout.size()).write_volatile(0);
+        dealloc(ptr, layout);
+    }
+
+    serial_println!("{}", Red("[overflow not detected]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "heap-debug: redzone after")
+}
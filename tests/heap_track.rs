@@ -0,0 +1,62 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bib_os::{
+    allocator::{self, track},
+    memory,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn balanced_code_does_not_leak() {
+    track::assert_no_leaks(|| {
+        let vec: Vec<u64> = (0..100).collect();
+        let boxed = Box::new(vec.iter().sum::<u64>());
+        assert_eq!(*boxed, 4950);
+    });
+}
+
+#[test_case]
+fn live_allocations_are_counted() {
+    let before = track::live_allocations();
+    let boxes: Vec<Box<u64>> = (0..10).map(Box::new).collect();
+    // the boxes and the vector
+    assert_eq!(track::live_allocations(), before + 11);
+    drop(boxes);
+    assert_eq!(track::live_allocations(), before);
+}
+
+#[test_case]
+fn allocations_are_tagged() {
+    let leaked = track::tagged("test", || Box::leak(Box::new(42u64)));
+    assert_eq!(*leaked, 42);
+    assert_eq!(track::live_allocations_tagged("test"), 1);
+    track::dump();
+
+    drop(unsafe { Box::from_raw(leaked) });
+    assert_eq!(track::live_allocations_tagged("test"), 0);
+}
@@ -0,0 +1,74 @@
+#![feature(abi_x86_interrupt)]
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bib_os::allocator;
+use bib_os::interrupts::{PIC_1_OFFSET, PICS};
+use bib_os::memory;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+    TEST_IDT.load();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt[usize::from(PIC_1_OFFSET)].set_handler_fn(allocating_timer_handler);
+        idt
+    };
+}
+
+/// The number of timer interrupts that allocated successfully.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+extern "x86-interrupt" fn allocating_timer_handler(_stack_frame: InterruptStackFrame) {
+    let values: Vec<usize> = (0..64).collect();
+    let boxed = Box::new(values.iter().sum::<usize>());
+    if *boxed == 63 * 64 / 2 {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET);
+    }
+}
+
+#[test_case]
+fn timer_allocates_while_heap_is_busy() {
+    let start = TICKS.load(Ordering::Relaxed);
+    let mut rounds = 0usize;
+    while TICKS.load(Ordering::Relaxed) < start + 20 {
+        let mut vec = Vec::new();
+        for i in 0..256 {
+            vec.push(Box::new(i));
+        }
+        assert!(vec.iter().enumerate().all(|(i, x)| **x == i));
+        rounds += 1;
+    }
+    assert!(rounds > 0);
+}
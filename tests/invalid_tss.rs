@@ -0,0 +1,83 @@
+#![feature(abi_x86_interrupt)]
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{QemuExitCode, Red, exit_qemu, hlt_loop, serial_print, serial_println};
+use core::{arch::asm, panic::PanicInfo};
+use lazy_static::lazy_static;
+use x86_64::{
+    instructions::{
+        segmentation::{CS, Segment},
+        tables::{load_tss, sidt},
+    },
+    structures::{
+        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        idt::{InterruptDescriptorTable, InterruptStackFrame},
+        tss::TaskStateSegment,
+    },
+};
+
+/// A vector without a handler in the kernel's IDT.
+const IST_VECTOR: usize = 0x80;
+/// A TSS limit that ends before the first interrupt stack table entry at
+/// offset 0x24.
+const TRUNCATED_LIMIT: u64 = 0x23;
+
+static TSS: TaskStateSegment = TaskStateSegment::new();
+
+lazy_static! {
+    /// A GDT whose TSS descriptor leaves out the interrupt stack table.
+    static ref TEST_GDT: (GlobalDescriptorTable, SegmentSelector, SegmentSelector) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let Descriptor::SystemSegment(low, high) = Descriptor::tss_segment(&TSS) else {
+            unreachable!()
+        };
+        // the limit is split into bits 0..16 and 48..52
+        let low = low & !0xffff & !(0xf << 48) | TRUNCATED_LIMIT;
+        let tss_selector = gdt.add_entry(Descriptor::SystemSegment(low, high));
+        (gdt, code_selector, tss_selector)
+    };
+
+    /// The kernel's IDT with an interrupt that switches to the first
+    /// interrupt stack.
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let kernel_idt = sidt().base.as_ptr::<InterruptDescriptorTable>();
+        let mut idt = unsafe { kernel_idt.read() };
+        unsafe { idt[IST_VECTOR].set_handler_fn(ist_handler).set_stack_index(0) };
+        idt
+    };
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("invalid_tss::invalid_tss...\t");
+
+    bib_os::init();
+    x86_64::instructions::interrupts::disable();
+    TEST_IDT.load();
+    TEST_GDT.0.load();
+    unsafe {
+        CS::set_reg(TEST_GDT.1);
+        load_tss(TEST_GDT.2);
+    }
+
+    // reading the interrupt stack from beyond the TSS limit raises #TS
+    unsafe { asm!("int {vector}", vector = const IST_VECTOR) };
+
+    serial_println!("{}", Red("[no exception]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+extern "x86-interrupt" fn ist_handler(_stack_frame: InterruptStackFrame) {
+    serial_println!("{}", Red("[interrupt delivered]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: INVALID TSS")
+}
@@ -0,0 +1,180 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bib_os::interrupts::{
+    self, PICS,
+    irq::{self, IrqError},
+};
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A line without a device under QEMU.
+const FREE_IRQ: u8 = 11;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    bib_os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+/// Raises the interrupt of `FREE_IRQ` in software.
+fn raise_free_irq() {
+    unsafe { asm!("int {vector}", vector = const irq::vector(FREE_IRQ)) };
+}
+
+fn is_masked(irq: u8) -> bool {
+    let masks = unsafe { PICS.lock().read_masks() };
+    masks[usize::from(irq / 8)] & (1 << (irq % 8)) != 0
+}
+
+static FIRST_CALLS: AtomicUsize = AtomicUsize::new(0);
+static SECOND_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn first_handler(irq: u8) {
+    assert_eq!(irq, FREE_IRQ);
+    FIRST_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn second_handler(_irq: u8) {
+    SECOND_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Distinct handlers, as a handler can only be registered once per line.
+static COUNTED_CALLS: [AtomicUsize; 8] = [const { AtomicUsize::new(0) }; 8];
+
+fn counting_handler<const N: usize>(_irq: u8) {
+    COUNTED_CALLS[N].fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn shared_line_calls_all_handlers() {
+    let first = irq::register(FREE_IRQ, first_handler).unwrap();
+    let second = irq::register(FREE_IRQ, second_handler).unwrap();
+    raise_free_irq();
+    assert_eq!(FIRST_CALLS.load(Ordering::Relaxed), 1);
+    assert_eq!(SECOND_CALLS.load(Ordering::Relaxed), 1);
+
+    irq::unregister(first).unwrap();
+    raise_free_irq();
+    assert_eq!(FIRST_CALLS.load(Ordering::Relaxed), 1);
+    assert_eq!(SECOND_CALLS.load(Ordering::Relaxed), 2);
+
+    irq::unregister(second).unwrap();
+    assert_eq!(irq::unregister(second), Err(IrqError::NotFound));
+}
+
+#[test_case]
+fn line_is_unmasked_while_handled() {
+    assert!(is_masked(FREE_IRQ));
+    let first = irq::register(FREE_IRQ, first_handler).unwrap();
+    let second = irq::register(FREE_IRQ, second_handler).unwrap();
+    assert!(!is_masked(FREE_IRQ));
+    assert!(!is_masked(irq::CASCADE));
+
+    irq::unregister(first).unwrap();
+    assert!(!is_masked(FREE_IRQ));
+    irq::unregister(second).unwrap();
+    assert!(is_masked(FREE_IRQ));
+    assert!(!irq::is_handled(FREE_IRQ));
+}
+
+#[test_case]
+fn invalid_registrations_fail() {
+    assert_eq!(
+        irq::register(irq::IRQ_LINES, first_handler),
+        Err(IrqError::InvalidLine)
+    );
+    assert_eq!(
+        irq::register(irq::CASCADE, first_handler),
+        Err(IrqError::InvalidLine)
+    );
+
+    let handlers: [irq::IrqHandler; 8] = [
+        counting_handler::<0>,
+        counting_handler::<1>,
+        counting_handler::<2>,
+        counting_handler::<3>,
+        counting_handler::<4>,
+        counting_handler::<5>,
+        counting_handler::<6>,
+        counting_handler::<7>,
+    ];
+    let mut ids = [None; 8];
+    let result = ids.iter_mut().zip(handlers).try_for_each(|(id, handler)| {
+        *id = Some(irq::register(FREE_IRQ, handler)?);
+        Ok(())
+    });
+    assert_eq!(result, Err(IrqError::TooManyHandlers));
+    for id in ids.into_iter().flatten() {
+        irq::unregister(id).unwrap();
+    }
+}
+
+#[test_case]
+fn handlers_are_registered_once_per_line() {
+    let id = irq::register(FREE_IRQ, second_handler).unwrap();
+    assert_eq!(
+        irq::register(FREE_IRQ, second_handler),
+        Err(IrqError::AlreadyRegistered)
+    );
+    irq::unregister(id).unwrap();
+}
+
+#[test_case]
+fn stale_id_does_not_unregister_reused_slot() {
+    let stale = irq::register(FREE_IRQ, first_handler).unwrap();
+    irq::unregister(stale).unwrap();
+    let current = irq::register(FREE_IRQ, second_handler).unwrap();
+
+    assert_eq!(irq::unregister(stale), Err(IrqError::NotFound));
+    assert!(irq::is_handled(FREE_IRQ));
+    irq::unregister(current).unwrap();
+    assert!(!irq::is_handled(FREE_IRQ));
+}
+
+static SPURIOUS_LINE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn spurious_line_handler(_irq: u8) {
+    SPURIOUS_LINE_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn spurious_interrupts_are_ignored() {
+    // raised in software, so the PIC does not mark the line as in service
+    // like for a spurious interrupt
+    let id = irq::register(7, spurious_line_handler).unwrap();
+    unsafe { asm!("int {vector}", vector = const irq::vector(7)) };
+    assert_eq!(SPURIOUS_LINE_CALLS.load(Ordering::Relaxed), 0);
+    irq::unregister(id).unwrap();
+}
+
+#[test_case]
+fn init_keeps_registered_handlers() {
+    interrupts::init_irqs();
+    assert!(irq::is_handled(irq::TIMER));
+    assert!(irq::is_handled(irq::KEYBOARD));
+}
+
+#[test_case]
+fn timer_and_keyboard_are_registered() {
+    assert!(irq::is_handled(irq::TIMER));
+    assert!(irq::is_handled(irq::KEYBOARD));
+
+    let start = interrupts::ticks();
+    while interrupts::ticks() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+}
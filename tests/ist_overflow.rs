@@ -0,0 +1,122 @@
+#![feature(abi_x86_interrupt)]
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{
+    Green, QemuExitCode, Red, exit_qemu, gdt, hlt_loop, memory, serial_print, serial_println,
+};
+use bootloader::{BootInfo, entry_point};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use lazy_static::lazy_static;
+use memory::vmm;
+use x86_64::{
+    registers::control::Cr2,
+    structures::{
+        idt::{InterruptDescriptorTable, InterruptStackFrame},
+        paging::{Page, PageTableFlags},
+    },
+};
+
+/// The byte the page below the guard page is filled with.
+const CANARY: u8 = 0xa5;
+
+/// Set when the double fault handler was entered the first time.
+static OVERFLOWING: AtomicBool = AtomicBool::new(false);
+/// The start address of the mapped page directly below the guard page.
+static NEIGHBOUR: AtomicU64 = AtomicU64::new(0);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("ist_overflow::double_fault_stack_overflow_hits_guard_page...\t");
+
+    gdt::init();
+    init_test_idt();
+    unsafe { memory::init_global(boot_info) };
+
+    // the first fit places the stack's guard page right above this page
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let neighbour = vmm::reserve(1, flags).expect("failed to reserve neighbour page");
+    vmm::map(&neighbour).expect("failed to map neighbour page");
+    unsafe {
+        neighbour
+            .start()
+            .as_mut_ptr::<u8>()
+            .write_bytes(CANARY, 4096)
+    };
+    NEIGHBOUR.store(neighbour.start().as_u64(), Ordering::Relaxed);
+
+    gdt::init_ist_stacks().expect("interrupt stack initialization failed");
+    let stack = gdt::ist_stack(gdt::DOUBLE_FAULT_IST_INDEX).expect("no guard-paged stack");
+    assert_eq!(stack.guard_page(), neighbour.pages().end);
+
+    // a page fault without handler escalates to a double fault
+    let unmapped = vmm::reserve(1, flags).expect("failed to reserve page");
+    unsafe { unmapped.start().as_mut_ptr::<u64>().write_volatile(1) };
+
+    serial_println!("{}", Red("[no double fault]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+#[allow(unconditional_recursion)]
+fn overflow() {
+    overflow(); // for each recursion, the return address is pushed
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
+
+/// Overflows the double fault stack on the first call. The fault on the
+/// guard page can not be delivered on the overflowed stack, so it escalates
+/// to another double fault, which starts again at the top of the stack.
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    if !OVERFLOWING.swap(true, Ordering::Relaxed) {
+        overflow();
+    }
+
+    let stack = gdt::ist_stack(gdt::DOUBLE_FAULT_IST_INDEX).unwrap();
+    let hit_guard_page = Page::containing_address(Cr2::read()) == stack.guard_page();
+    let neighbour = NEIGHBOUR.load(Ordering::Relaxed) as *const [u8; 4096];
+    let neighbour_intact = unsafe { (*neighbour).iter().all(|&b| b == CANARY) };
+
+    if hit_guard_page && neighbour_intact {
+        serial_println!("{}", Green("[ok]"));
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!(
+            "{} guard page hit: {}, neighbour intact: {}",
+            Red("[failed]"),
+            hit_guard_page,
+            neighbour_intact
+        );
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop()
+}
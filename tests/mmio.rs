@@ -0,0 +1,51 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bib_os::memory::{
+    self,
+    mmio::{MmioRegion, UNCACHED},
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::PhysAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+/// The VGA text buffer is device memory that exists on every test machine.
+const VGA_BUFFER: u64 = 0xb8000;
+const VGA_BUFFER_SIZE: u64 = 80 * 25 * 2;
+
+#[test_case]
+fn read_back_device_memory() {
+    let mut vga = unsafe { MmioRegion::map(PhysAddr::new(VGA_BUFFER), VGA_BUFFER_SIZE, UNCACHED) }
+        .expect("mapping failed");
+    let last = VGA_BUFFER_SIZE - 2;
+    vga.write_u16(last, 0x0f21);
+    assert_eq!(vga.read_u16(last), 0x0f21);
+    assert_eq!(vga.read_u8(last), 0x21);
+}
+
+#[test_case]
+fn unaligned_physical_start() {
+    let vga = unsafe { MmioRegion::map(PhysAddr::new(VGA_BUFFER + 2), 2, UNCACHED) }
+        .expect("mapping failed");
+    assert_eq!(vga.base().as_u64() % 4096, 2);
+    assert_eq!(vga.size(), 2);
+}
@@ -0,0 +1,30 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{QemuExitCode, Red, exit_qemu, hlt_loop, memory, serial_print, serial_println};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+/// An address that is neither mapped nor part of a demand paged region.
+const UNMAPPED: u64 = 0x1234_5678_9000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("page_fault::page_fault...\t");
+
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+
+    unsafe { (UNMAPPED as *const u64).read_volatile() };
+
+    serial_println!("{}", Red("[no exception]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: PAGE FAULT at VirtAddr(0x123456789000)")
+}
@@ -0,0 +1,55 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bib_os::interrupts::{self, Controller, PICS, apic, irq};
+use bib_os::memory;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    let controller = interrupts::init_controller().expect("controller initialization failed");
+    assert_eq!(controller, Controller::Pic);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+fn is_masked(irq: u8) -> bool {
+    let masks = unsafe { PICS.lock().read_masks() };
+    masks[usize::from(irq / 8)] & (1 << (irq % 8)) != 0
+}
+
+#[test_case]
+fn pics_deliver_interrupts() {
+    assert!(apic::is_supported());
+    assert!(!apic::is_active());
+    assert_eq!(interrupts::controller(), Controller::Pic);
+}
+
+#[test_case]
+fn handled_lines_are_unmasked() {
+    assert!(!is_masked(irq::TIMER));
+    assert!(!is_masked(irq::KEYBOARD));
+    assert!(!is_masked(irq::CASCADE));
+}
+
+#[test_case]
+fn pit_ticks() {
+    let start = interrupts::ticks();
+    while interrupts::ticks() < start + 10 {
+        x86_64::instructions::hlt();
+    }
+}
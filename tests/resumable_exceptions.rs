@@ -0,0 +1,49 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bib_os::vga_buffer::STDERR;
+use core::{arch::asm, panic::PanicInfo};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    bib_os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+/// Returns whether a line on the screen starts with `prefix`.
+fn on_screen(prefix: &str) -> bool {
+    let stderr = STDERR.lock();
+    (0..)
+        .map_while(|row| stderr.get_row_bytes(row).ok())
+        .any(|row| row.starts_with(prefix.as_bytes()))
+}
+
+#[test_case]
+fn breakpoint_is_reported_and_resumed() {
+    x86_64::instructions::interrupts::int3();
+    assert!(on_screen("EXCEPTION: BREAKPOINT (#BP, vector 3)"));
+}
+
+#[test_case]
+fn registers_are_reported() {
+    unsafe { asm!("int3", in("rax") 0xdead_beef_u64, in("r13") 0x1234_u64) };
+    assert!(on_screen("RAX: 0x00000000deadbeef"));
+    assert!(on_screen("R13: 0x0000000000001234"));
+}
+
+#[test_case]
+fn debug_exception_is_reported_and_resumed() {
+    // int1, which the assembler has no mnemonic for
+    unsafe { asm!(".byte 0xf1") };
+    assert!(on_screen("EXCEPTION: DEBUG (#DB, vector 1)"));
+}
@@ -0,0 +1,50 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{QemuExitCode, Red, exit_qemu, hlt_loop, serial_print, serial_println};
+use core::{arch::asm, panic::PanicInfo};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+/// The default MXCSR with the zero divide exception unmasked.
+const MXCSR: u32 = 0x1f80 & !(1 << 9);
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("simd_floating_point::simd_floating_point...\t");
+
+    bib_os::init();
+
+    // enable SSE and its exceptions
+    unsafe {
+        Cr0::update(|flags| {
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+        });
+        Cr4::update(|flags| {
+            flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+    }
+
+    // divide 1.0 by zero; the kernel is built without SSE, so the compiler
+    // keeps nothing in the xmm registers that are overwritten
+    unsafe {
+        asm!(
+            "ldmxcsr [{mxcsr}]",
+            "movd xmm0, {one:e}",
+            "xorps xmm1, xmm1",
+            "divss xmm0, xmm1",
+            mxcsr = in(reg) &MXCSR,
+            one = in(reg) 1.0f32.to_bits(),
+        );
+    }
+
+    serial_println!("{}", Red("[no exception]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: SIMD FLOATING-POINT EXCEPTION")
+}
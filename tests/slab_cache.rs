@@ -0,0 +1,110 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bib_os::allocator::{
+    self,
+    slab::{SlabBox, SlabCache},
+};
+use bib_os::memory;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Node {
+    value: u64,
+    next: Option<usize>,
+}
+
+#[test_case]
+fn freed_objects_are_reused() {
+    static NODES: SlabCache<Node> = SlabCache::new("node");
+
+    let first = NODES.alloc(Node {
+        value: 1,
+        next: None,
+    });
+    let addr = &*first as *const Node;
+    drop(first);
+
+    let second = NODES.alloc(Node {
+        value: 2,
+        next: Some(1),
+    });
+    assert_eq!(&*second as *const Node, addr);
+    assert_eq!(second.value, 2);
+    assert_eq!(second.next, Some(1));
+}
+
+#[test_case]
+fn constructor_initializes_objects() {
+    static COUNTERS: SlabCache<[u64; 4]> = SlabCache::with_constructor("counters", || [7; 4]);
+
+    let mut counters = COUNTERS.alloc_constructed();
+    assert_eq!(*counters, [7; 4]);
+    counters[0] += 1;
+    assert_eq!(*counters, [8, 7, 7, 7]);
+}
+
+#[test_case]
+fn stats_track_objects_and_slabs() {
+    static BLOCKS: SlabCache<[u8; 100]> = SlabCache::new("blocks");
+
+    let empty = BLOCKS.stats();
+    assert_eq!(empty.name, "blocks");
+    assert_eq!(empty.slabs, 0);
+
+    // enough objects for several slabs
+    let objects: Vec<_> = (0..100u8).map(|i| BLOCKS.alloc([i; 100])).collect();
+    assert!(objects.iter().enumerate().all(|(i, o)| o[99] == i as u8));
+    let stats = BLOCKS.stats();
+    assert_eq!(stats.objects, 100);
+    assert_eq!(stats.peak_objects, 100);
+    assert!(stats.object_size >= 100);
+    assert!(stats.slabs > 1);
+    // only the last slab has free objects
+    assert!(stats.free_objects < stats.slab_size / stats.object_size);
+
+    // all slabs are given back once their objects are freed
+    drop(objects);
+    let stats = BLOCKS.stats();
+    assert_eq!(stats.objects, 0);
+    assert_eq!(stats.peak_objects, 100);
+    assert_eq!(stats.slabs, 0);
+    assert_eq!(stats.free_objects, 0);
+}
+
+#[test_case]
+fn raw_objects_are_freed_by_their_box() {
+    static VALUES: SlabCache<u64> = SlabCache::new("values");
+
+    let ptr = VALUES.alloc(42).into_raw();
+    assert_eq!(VALUES.stats().objects, 1);
+
+    let value = unsafe { SlabBox::from_raw(ptr, &VALUES) };
+    assert_eq!(*value, 42);
+    drop(value);
+    assert_eq!(VALUES.stats().objects, 0);
+}
@@ -0,0 +1,30 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::serial_print;
+use core::{arch::asm, panic::PanicInfo};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("stack_segment_fault::stack_segment_fault...\t");
+
+    bib_os::init();
+
+    // a non-canonical address accessed through rbp faults with #SS; rbp is
+    // never restored, so execution must not continue after the access
+    unsafe {
+        asm!(
+            "mov rbp, {address}",
+            "mov rax, [rbp]",
+            "ud2",
+            address = in(reg) 0x8000_0000_0000_0000u64,
+            options(noreturn),
+        )
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: STACK-SEGMENT FAULT")
+}
@@ -0,0 +1,129 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(bib_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bib_os::memory::{
+    self, FRAME_ALLOCATOR,
+    vmm::{self, VmmError},
+    walk,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{PageSize, PageTableFlags, Size2MiB},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    bib_os::init();
+    unsafe { memory::init_global(boot_info) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames()
+}
+
+#[test_case]
+fn reservations_do_not_overlap() {
+    let a = vmm::reserve(8, PageTableFlags::WRITABLE).expect("reserve failed");
+    let b = vmm::reserve(8, PageTableFlags::WRITABLE).expect("reserve failed");
+    assert!(a.start() + a.size() <= b.start() || b.start() + b.size() <= a.start());
+
+    let result = vmm::reserve_at(a.pages().start + 4, 8, PageTableFlags::WRITABLE);
+    assert!(matches!(result, Err(VmmError::Overlap)));
+
+    unsafe {
+        vmm::release(a).expect("release failed");
+        vmm::release(b).expect("release failed");
+    }
+}
+
+#[test_case]
+fn unmap_returns_frames() {
+    let before = free_frames();
+    let region = vmm::reserve(16, PageTableFlags::WRITABLE).expect("reserve failed");
+    vmm::map(&region).expect("map failed");
+
+    let ptr: *mut u64 = region.start().as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+    assert!(before - free_frames() >= 16);
+
+    unsafe { vmm::release(region).expect("release failed") };
+    // page tables created for the mapping are freed as well
+    assert_eq!(free_frames(), before);
+}
+
+#[test_case]
+fn large_aligned_mapping_uses_huge_pages() {
+    let before = free_frames();
+    // two 2 MiB pages followed by three 4 KiB pages
+    let region = vmm::reserve(2 * 512 + 3, PageTableFlags::WRITABLE).expect("reserve failed");
+    assert!(region.start().is_aligned(Size2MiB::SIZE));
+    vmm::map(&region).expect("map failed");
+
+    for offset in [0, Size2MiB::SIZE] {
+        let page_walk = walk::walk(region.start() + offset).expect("walk failed");
+        let leaf = page_walk.leaf().expect("address not mapped");
+        assert_eq!(leaf.level, 2);
+        assert!(leaf.flags.contains(PageTableFlags::HUGE_PAGE));
+    }
+    let tail = region.start() + 2 * Size2MiB::SIZE;
+    let page_walk = walk::walk(tail).expect("walk failed");
+    assert_eq!(page_walk.leaf().expect("address not mapped").level, 1);
+
+    let ptr: *mut u64 = (region.start() + Size2MiB::SIZE + 0x1238u64).as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+
+    unsafe { vmm::release(region).expect("release failed") };
+    assert!(walk::walk(region.start()).unwrap().translate().is_none());
+    assert_eq!(free_frames(), before);
+}
+
+#[test_case]
+fn walk_reports_mapping() {
+    let region = vmm::reserve(4, PageTableFlags::WRITABLE).expect("reserve failed");
+    vmm::map(&region).expect("map failed");
+
+    let addr = region.start() + 0x1234u64;
+    let page_walk = walk::walk(addr).expect("walk failed");
+    let leaf = page_walk.leaf().expect("address not mapped");
+    assert_eq!(leaf.level, 1);
+    assert!(leaf.flags.contains(PageTableFlags::WRITABLE));
+    assert_eq!(page_walk.translate().unwrap().as_u64() & 0xfff, 0x234);
+
+    let mut found = false;
+    walk::for_each_mapping(|range| {
+        let end = range.start.as_u64() + range.size;
+        found |= range.start <= addr && addr.as_u64() < end;
+    });
+    assert!(found);
+
+    unsafe { vmm::release(region).expect("release failed") };
+    let page_walk = walk::walk(addr).expect("walk failed");
+    assert!(page_walk.translate().is_none());
+}
+
+#[test_case]
+fn walk_unmapped_address() {
+    let page_walk = walk::walk(VirtAddr::new(0xdead_b000)).expect("walk failed");
+    assert!(page_walk.translate().is_none());
+}
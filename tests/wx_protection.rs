@@ -0,0 +1,83 @@
+#![feature(abi_x86_interrupt)]
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{
+    Green, QemuExitCode, Red, exit_qemu, hlt_loop, memory::walk, serial_print, serial_println,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use x86_64::{
+    VirtAddr,
+    registers::control::Cr2,
+    structures::{
+        idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+        paging::PageTableFlags,
+    },
+};
+
+/// A value that is placed in the read-only data of the kernel.
+static READ_ONLY: u64 = 0x1234_5678;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("wx_protection::write_to_rodata...\t");
+
+    bib_os::gdt::init();
+    init_test_idt();
+    unsafe { bib_os::memory::init_global(boot_info) };
+
+    // no other memory, like the stack or the boot info, is left writable and
+    // executable
+    walk::for_each_mapping(|range| {
+        let writable = range.flags.contains(PageTableFlags::WRITABLE);
+        if writable && !range.flags.contains(PageTableFlags::NO_EXECUTE) {
+            serial_println!("{}", Red("[writable and executable]"));
+            serial_println!("{:?}", range);
+            exit_qemu(QemuExitCode::Failed);
+            hlt_loop();
+        }
+    });
+
+    // writing to read-only data must fault
+    unsafe { (&raw const READ_ONLY as *mut u64).write_volatile(0) };
+
+    serial_println!("{}", Red("[write did not fault]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let expected = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if Cr2::read() == VirtAddr::from_ptr(&raw const READ_ONLY) && error_code.contains(expected) {
+        serial_println!("{}", Green("[ok]"));
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("{}", Red("[unexpected page fault]"));
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop()
+}
@@ -0,0 +1,47 @@
+#![allow(clippy::empty_loop)]
+#![no_std]
+#![no_main]
+
+use bib_os::{QemuExitCode, Red, exit_qemu, hlt_loop, serial_print, serial_println};
+use core::{arch::asm, panic::PanicInfo};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+/// The default x87 control word with the zero divide exception unmasked.
+const CONTROL_WORD: u16 = 0x037f & !(1 << 2);
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("x87_floating_point::x87_floating_point...\t");
+
+    bib_os::init();
+
+    // report x87 errors as exceptions instead of through the legacy IRQ 13
+    unsafe {
+        Cr0::update(|flags| {
+            flags.insert(Cr0Flags::NUMERIC_ERROR | Cr0Flags::MONITOR_COPROCESSOR);
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+        });
+    }
+
+    // divide by zero, the error is raised by the next waiting instruction
+    unsafe {
+        asm!(
+            "fninit",
+            "fldcw [{}]",
+            "fld1",
+            "fldz",
+            "fdivp",
+            "fwait",
+            in(reg) &CONTROL_WORD,
+        );
+    }
+
+    serial_println!("{}", Red("[no exception]"));
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    bib_os::test_expected_panic_handler(info, "EXCEPTION: X87 FLOATING-POINT ERROR")
+}